@@ -0,0 +1,125 @@
+use encoding_rs::{Encoding, UTF_8};
+
+/// How far into the body an HTML `<meta>` charset is looked for.
+const SNIFF_LEN: usize = 1024;
+
+/// Decodes `body` to text. The encoding comes from the `charset` parameter of
+/// `content_type`, then a byte order mark, then an HTML `<meta>` tag when
+/// `content_type` is `text/html`, then `default`; unknown labels are skipped
+/// and UTF-8 is the last resort.
+/// Returns the text and the name of the encoding used.
+// `map_or` rather than `Option::is_some_and`, which needs Rust 1.70.
+#[allow(clippy::unnecessary_map_or)]
+pub fn decode(body: &[u8], content_type: Option<&str>, default: &str) -> (String, &'static str) {
+    let encoding = content_type
+        .and_then(charset_param)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .or_else(|| Encoding::for_bom(body).map(|(encoding, _)| encoding))
+        .or_else(|| {
+            meta_charset(body)
+                .filter(|_| content_type.map_or(false, is_html))
+                .and_then(|label| Encoding::for_label(label.as_bytes()))
+        })
+        .or_else(|| Encoding::for_label(default.as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _) = encoding.decode_with_bom_removal(body);
+    (text.into_owned(), encoding.name())
+}
+
+/// Value of the `charset` parameter of a `Content-Type` header.
+pub fn charset_param(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let mut param = param.splitn(2, '=');
+        let name = param.next()?.trim();
+        if name.eq_ignore_ascii_case("charset") {
+            Some(param.next()?.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn is_html(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    media_type.trim().eq_ignore_ascii_case("text/html")
+}
+
+/// Charset from `<meta charset="...">` or `<meta http-equiv="Content-Type"
+/// content="...; charset=...">` near the start of an HTML document.
+fn meta_charset(body: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(SNIFF_LEN)]).to_ascii_lowercase();
+    head.split("<meta").skip(1).find_map(|tag| {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let value = &tag[tag.find("charset")? + "charset".len()..];
+        let value = value.trim_start().strip_prefix('=')?;
+        let value = value.trim_start().trim_start_matches(['"', '\''].as_ref());
+        let end = value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+            .unwrap_or(value.len());
+        if end == 0 {
+            None
+        } else {
+            Some(value[..end].to_string())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVET_1251: &[u8] = &[0xcf, 0xf0, 0xe8, 0xe2, 0xe5, 0xf2];
+    const PRIVET_KOI8: &[u8] = &[0xf0, 0xd2, 0xc9, 0xd7, 0xc5, 0xd4];
+
+    #[test]
+    fn charset_from_header() {
+        assert_eq!(
+            decode(
+                PRIVET_1251,
+                Some("text/html; charset=\"Windows-1251\""),
+                "utf-8"
+            ),
+            ("Привет".to_string(), "windows-1251")
+        );
+        assert_eq!(
+            charset_param("text/plain; format=flowed; charset=koi8-r"),
+            Some("koi8-r".to_string())
+        );
+        assert_eq!(charset_param("text/plain"), None);
+    }
+
+    #[test]
+    fn charset_from_meta() {
+        let mut body = b"<html><head><META http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\"></head>".to_vec();
+        body.extend_from_slice(PRIVET_KOI8);
+        let (text, encoding) = decode(&body, Some("text/html"), "utf-8");
+        assert!(text.ends_with("Привет"));
+        assert_eq!(encoding, "KOI8-R");
+
+        let (_, encoding) = decode(&body, Some("text/plain"), "utf-8");
+        assert_eq!(encoding, "UTF-8");
+        let (_, encoding) = decode(&body, None, "utf-8");
+        assert_eq!(encoding, "UTF-8");
+
+        assert_eq!(
+            meta_charset(b"<meta charset='windows-1251'/>"),
+            Some("windows-1251".to_string())
+        );
+    }
+
+    #[test]
+    fn charset_from_bom_and_default() {
+        assert_eq!(
+            decode(b"\xef\xbb\xbfhello", None, "windows-1251"),
+            ("hello".to_string(), "UTF-8")
+        );
+        assert_eq!(
+            decode(PRIVET_1251, None, "windows-1251"),
+            ("Привет".to_string(), "windows-1251")
+        );
+        assert_eq!(
+            decode(b"hello", None, "unknown"),
+            ("hello".to_string(), "UTF-8")
+        );
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use tokio::net::TcpStream;
+use tokio::time;
+use uri::Uri;
+
+use crate::auth::{proxy_auth_required, user_info, Authenticator, Credentials, Target};
+use crate::decoder::ResponseDecoder;
+use crate::error::{Error, Result};
+use crate::request::Request;
+use crate::response::Response;
+use crate::socks5::Negotiation;
+use crate::stream::{http_connect, MaybeHttpsStream};
+use crate::{socks4, socks5};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Way of going through a proxy a `Checker` tries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// Plain http requests in absolute-form.
+    Http,
+    /// An http CONNECT tunnel.
+    Connect,
+    Socks4,
+    Socks5,
+}
+
+impl Protocol {
+    fn scheme(self) -> &'static str {
+        match self {
+            Protocol::Http | Protocol::Connect => "http",
+            Protocol::Socks4 => "socks4",
+            Protocol::Socks5 => "socks5",
+        }
+    }
+}
+
+/// How much a proxy tells the judge about its client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anonymity {
+    /// The client address is passed on.
+    Transparent,
+    /// The proxy shows itself with `Via`, `X-Forwarded-For` or `Forwarded`.
+    Anonymous,
+    /// None of those headers reach the judge.
+    Elite,
+}
+
+/// What came of the credentials in the proxy URI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthStatus {
+    NotRequired,
+    /// The proxy asked for credentials and the URI has none.
+    Required,
+    Accepted,
+    Rejected,
+}
+
+/// Time spent in each phase of a check, `handshake` is the CONNECT or SOCKS
+/// exchange and `tls` the one with an `https` judge.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timings {
+    pub connect: Duration,
+    pub handshake: Option<Duration>,
+    pub tls: Option<Duration>,
+    pub response: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.connect
+            + self.handshake.unwrap_or_default()
+            + self.tls.unwrap_or_default()
+            + self.response
+    }
+}
+
+/// Outcome of checking one proxy. The exit IP and anonymity come from the
+/// first protocol that worked, in the order they are tried.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub proxy: Uri,
+    /// A TCP connection to the proxy could be opened.
+    pub reachable: bool,
+    pub protocols: Vec<(Protocol, Timings)>,
+    pub errors: Vec<(Protocol, Error)>,
+    /// `None` when no protocol got as far as authentication.
+    pub auth: Option<AuthStatus>,
+    pub exit_ip: Option<String>,
+    pub anonymity: Option<Anonymity>,
+}
+
+impl Report {
+    pub fn is_working(&self) -> bool {
+        !self.protocols.is_empty()
+    }
+
+    pub fn supports(&self, protocol: Protocol) -> bool {
+        self.protocols
+            .iter()
+            .any(|(supported, _)| *supported == protocol)
+    }
+}
+
+/// Checks proxies by fetching a judge page through them. The judge echoes
+/// the request headers it got, one `Name: value` per line, and the client
+/// address as `REMOTE_ADDR`. Lines of `NAME = value`, with `HTTP_` before
+/// header names, as azenv-style judges print them, are read as well. The
+/// judge should be `http` for proxies to be able to add their headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checker {
+    judge: Uri,
+    real_ip: Option<String>,
+    protocols: Vec<Protocol>,
+    timeout: Duration,
+}
+
+impl Checker {
+    pub fn new(judge: Uri) -> Checker {
+        Checker {
+            judge,
+            real_ip: None,
+            protocols: vec![
+                Protocol::Http,
+                Protocol::Connect,
+                Protocol::Socks5,
+                Protocol::Socks4,
+            ],
+            timeout: TIMEOUT,
+        }
+    }
+
+    /// Address of this host as the judge sees it without a proxy, asked of
+    /// the judge on every check when not set.
+    pub fn real_ip(mut self, ip: &str) -> Checker {
+        self.real_ip = Some(ip.to_string());
+        self
+    }
+
+    /// Protocols tried, in this order.
+    pub fn protocols(mut self, protocols: &[Protocol]) -> Checker {
+        self.protocols = protocols.to_vec();
+        self
+    }
+
+    /// Time allowed to each protocol and to the judge.
+    pub fn timeout(mut self, timeout: Duration) -> Checker {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Address of this host as echoed by the judge when asked directly.
+    pub async fn own_ip(&self) -> Result<String> {
+        let fetch = async {
+            let mut stream = MaybeHttpsStream::new(&self.judge).await?;
+            let request = Request::new(&self.judge, None);
+            stream.send_msg(&request.to_vec()).await?;
+            let response = stream.read_response(ResponseDecoder::new()).await?;
+            judge_field(&judge_fields(&response.text()?), "REMOTE_ADDR").ok_or(Error::JudgeAddress)
+        };
+        time::timeout(self.timeout, fetch)
+            .await
+            .unwrap_or_else(|_| Err(timed_out()))
+    }
+
+    pub async fn check(&self, proxy: &Uri) -> Report {
+        let real_ip = match &self.real_ip {
+            Some(ip) => Some(ip.clone()),
+            None => self.own_ip().await.ok(),
+        };
+        self.check_from(proxy, real_ip.as_deref()).await
+    }
+
+    /// Checks `proxies` one after the other, asking the judge for the real IP
+    /// once.
+    pub async fn check_all<I>(&self, proxies: I) -> Vec<Report>
+    where
+        I: IntoIterator<Item = Uri>,
+    {
+        let real_ip = match &self.real_ip {
+            Some(ip) => Some(ip.clone()),
+            None => self.own_ip().await.ok(),
+        };
+        let mut reports = Vec::new();
+        for proxy in proxies {
+            reports.push(self.check_from(&proxy, real_ip.as_deref()).await);
+        }
+        reports
+    }
+
+    async fn check_from(&self, proxy: &Uri, real_ip: Option<&str>) -> Report {
+        let mut report = Report {
+            proxy: proxy.clone(),
+            reachable: false,
+            protocols: Vec::new(),
+            errors: Vec::new(),
+            auth: None,
+            exit_ip: None,
+            anonymity: None,
+        };
+        let mut authenticated = false;
+        for protocol in self.protocols.iter().copied() {
+            let mut connected = false;
+            let probe = time::timeout(self.timeout, self.probe(protocol, proxy, &mut connected))
+                .await
+                .unwrap_or_else(|_| Err(timed_out()));
+            report.reachable |= connected;
+            match probe {
+                Ok((timings, response, answered)) => {
+                    authenticated |= answered;
+                    if !report.is_working() {
+                        let fields = judge_fields(&response.text().unwrap_or_default());
+                        report.exit_ip = judge_field(&fields, "REMOTE_ADDR");
+                        report.anonymity = Some(anonymity(&fields, real_ip));
+                    }
+                    report.protocols.push((protocol, timings));
+                }
+                Err(err) => report.errors.push((protocol, err)),
+            }
+        }
+        report.auth = auth_status(&report, user_info(proxy).is_some(), authenticated);
+        report
+    }
+
+    /// Fetches the judge through `proxy` spoken to with `protocol`, answering
+    /// a 407 once with the credentials of the URI. Also tells whether the
+    /// proxy took credentials: an answered 407 or SOCKS5 authentication.
+    async fn probe(
+        &self,
+        protocol: Protocol,
+        proxy: &Uri,
+        connected: &mut bool,
+    ) -> Result<(Timings, Response, bool)> {
+        let proxy = with_scheme(proxy, protocol.scheme())?;
+        let credentials = Credentials::from_uri(&proxy);
+        let auth = credentials.as_ref().map(|auth| auth as &dyn Authenticator);
+        let mut retry = true;
+        loop {
+            let start = Instant::now();
+            let mut stream = TcpStream::connect(proxy.socket_addr()?).await?;
+            *connected = true;
+            let mut timings = Timings {
+                connect: start.elapsed(),
+                ..Timings::default()
+            };
+
+            let start = Instant::now();
+            let mut socks_auth = false;
+            match protocol {
+                Protocol::Http => (),
+                Protocol::Connect => {
+                    if !http_connect(&mut stream, &self.judge, auth, retry).await? {
+                        retry = false;
+                        continue;
+                    }
+                }
+                Protocol::Socks4 => socks4::handshake(&mut stream, &proxy, &self.judge).await?,
+                Protocol::Socks5 => {
+                    socks_auth = socks5::authenticated_handshake(&mut stream, &proxy, &self.judge)
+                        .await?
+                        .1;
+                }
+            }
+            let mut request = match protocol {
+                Protocol::Http => Request::new(&self.judge, Some(&proxy)),
+                _ => {
+                    timings.handshake = Some(start.elapsed());
+                    Request::new(&self.judge, None)
+                }
+            };
+
+            let start = Instant::now();
+            let mut stream = match protocol {
+                Protocol::Http => MaybeHttpsStream::from(stream),
+                _ => MaybeHttpsStream::maybe_ssl(&self.judge, stream, &[]).await?,
+            };
+            if protocol != Protocol::Http && self.judge.is_ssl() {
+                timings.tls = Some(start.elapsed());
+            }
+
+            if let (Protocol::Http, Some(auth)) = (protocol, auth) {
+                auth.before_send(&mut request, Target::Proxy)?;
+            }
+            let start = Instant::now();
+            stream.send_msg(&request.to_vec()).await?;
+            let decoder = ResponseDecoder::with_method(&request.get_method());
+            let response = stream.read_response(decoder).await?;
+            timings.response = start.elapsed();
+            match u16::from(response.status_code()) {
+                407 if protocol == Protocol::Http => match auth {
+                    Some(auth)
+                        if retry
+                            && auth.on_challenge(&mut request, &response, Target::Proxy)? =>
+                    {
+                        retry = false;
+                    }
+                    _ => return Err(proxy_auth_required(&response)),
+                },
+                _ if response.status_code().is_success() => {
+                    return Ok((timings, response, !retry || socks_auth))
+                }
+                code => return Err(Error::JudgeStatus(code)),
+            }
+        }
+    }
+}
+
+fn timed_out() -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "proxy check timed out",
+    ))
+}
+
+fn with_scheme(proxy: &Uri, scheme: &str) -> Result<Uri> {
+    let rest = proxy
+        .as_str()
+        .split_once("://")
+        .map_or(proxy.as_str(), |(_, rest)| rest);
+    Ok(format!("{}://{}", scheme, rest).parse()?)
+}
+
+/// `authenticated` when a working protocol got through by answering for
+/// credentials, a proxy letting the client in without asking is `NotRequired`
+/// even when the URI has some.
+fn auth_status(report: &Report, credentials: bool, authenticated: bool) -> Option<AuthStatus> {
+    let asked = report.errors.iter().any(|(_, err)| {
+        matches!(
+            err,
+            Error::ProxyAuthRequired { .. }
+                | Error::Socks5Negotiation(Negotiation::AuthRejected(_))
+                | Error::Socks5Negotiation(Negotiation::NoAcceptableMethods)
+        )
+    });
+    match (report.is_working(), asked, credentials) {
+        (true, ..) if authenticated => Some(AuthStatus::Accepted),
+        (true, ..) => Some(AuthStatus::NotRequired),
+        (false, true, true) => Some(AuthStatus::Rejected),
+        (false, true, false) => Some(AuthStatus::Required),
+        (false, false, _) => None,
+    }
+}
+
+/// Fields of a judge page, names in upper case with `_` for `-` and without
+/// the `HTTP_` prefix.
+fn judge_fields(body: &str) -> Vec<(String, String)> {
+    body.lines()
+        .filter_map(|line| {
+            let idx = match (line.find(':'), line.find('=')) {
+                (Some(colon), Some(equals)) => colon.min(equals),
+                (colon, equals) => colon.or(equals)?,
+            };
+            let name = line[..idx].trim().to_ascii_uppercase().replace('-', "_");
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return None;
+            }
+            let name = name.trim_start_matches("HTTP_").to_string();
+            Some((name, line[idx + 1..].trim().to_string()))
+        })
+        .collect()
+}
+
+fn judge_field(fields: &[(String, String)], name: &str) -> Option<String> {
+    fields
+        .iter()
+        .find(|(field, value)| field == name && !value.is_empty())
+        .map(|(_, value)| value.clone())
+}
+
+/// Transparent when the judge sees `real_ip` as the client address or it is
+/// among the addresses in the proxy headers, anonymous when there are any of
+/// those headers, elite otherwise.
+fn anonymity(fields: &[(String, String)], real_ip: Option<&str>) -> Anonymity {
+    let headers: Vec<&str> = fields
+        .iter()
+        .filter(|(name, _)| matches!(name.as_str(), "VIA" | "X_FORWARDED_FOR" | "FORWARDED"))
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let leaks = |ip: &str| {
+        headers.iter().any(|value| {
+            value
+                .split([',', ' ', ';', '=', '"', '[', ']'].as_ref())
+                .any(|token| token == ip)
+        })
+    };
+    match real_ip {
+        Some(ip) if leaks(ip) || judge_field(fields, "REMOTE_ADDR").as_deref() == Some(ip) => {
+            Anonymity::Transparent
+        }
+        _ if !headers.is_empty() => Anonymity::Anonymous,
+        _ => Anonymity::Elite,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const JUDGE: &str = "http://judge.invalid/";
+
+    async fn read_head(socket: &mut TcpStream) -> String {
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            header.push(socket.read_u8().await.unwrap());
+        }
+        String::from_utf8(header).unwrap()
+    }
+
+    /// Page of a judge seeing `head` from 198.51.100.1.
+    fn judge_page(head: &str) -> String {
+        let body = format!("REMOTE_ADDR = 198.51.100.1\n{}", head);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    /// Http proxy asking for credentials on every new connection, adding
+    /// `Via` and `X-Forwarded-For` to forwarded requests and acting as the
+    /// judge itself.
+    async fn serve(listener: TcpListener, connections: usize) {
+        for _ in 0..connections {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut socket).await;
+            if !head.contains("proxy-authorization: Basic dXNlcjpwYXNz") {
+                socket
+                    .write_all(
+                        b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\nContent-Length: 0\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                continue;
+            }
+            let page = if head.starts_with("CONNECT ") {
+                socket
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                judge_page(&read_head(&mut socket).await)
+            } else {
+                judge_page(&format!(
+                    "{}via: 1.1 proxy\r\nx-forwarded-for: 203.0.113.7\r\n",
+                    head.trim_end()
+                ))
+            };
+            socket.write_all(page.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn checker_anonymity() {
+        let fields = judge_fields(
+            "<pre>\nREMOTE_ADDR = 198.51.100.1\nHTTP_X_FORWARDED_FOR = 203.0.113.7, 10.0.0.1\nHost: judge\n</pre>",
+        );
+        assert_eq!(
+            judge_field(&fields, "REMOTE_ADDR"),
+            Some("198.51.100.1".to_string())
+        );
+        assert_eq!(
+            anonymity(&fields, Some("203.0.113.7")),
+            Anonymity::Transparent
+        );
+        assert_eq!(
+            anonymity(&fields, Some("203.0.113.70")),
+            Anonymity::Anonymous
+        );
+        let fields = judge_fields("Forwarded: for=\"[2001:db8::1]\";proto=http\n");
+        assert_eq!(
+            anonymity(&fields, Some("2001:db8::1")),
+            Anonymity::Transparent
+        );
+        assert_eq!(anonymity(&fields, None), Anonymity::Anonymous);
+        let fields = judge_fields("REMOTE_ADDR = 198.51.100.1\nUser-Agent: netc\n");
+        assert_eq!(anonymity(&fields, Some("203.0.113.7")), Anonymity::Elite);
+        assert_eq!(
+            anonymity(&fields, Some("198.51.100.1")),
+            Anonymity::Transparent
+        );
+    }
+
+    #[tokio::test]
+    async fn checker_http_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 4));
+        let checker = Checker::new(JUDGE.parse().unwrap())
+            .real_ip("203.0.113.7")
+            .protocols(&[Protocol::Http, Protocol::Connect]);
+        let proxy: Uri = format!("http://user:pass@{}", addr).parse().unwrap();
+        let report = checker.check(&proxy).await;
+        server.await.unwrap();
+
+        assert!(report.reachable);
+        assert!(report.supports(Protocol::Http) && report.supports(Protocol::Connect));
+        assert_eq!(report.errors, Vec::new());
+        assert_eq!(report.auth, Some(AuthStatus::Accepted));
+        assert_eq!(report.exit_ip, Some("198.51.100.1".to_string()));
+        assert_eq!(report.anonymity, Some(Anonymity::Transparent));
+        assert_eq!(report.protocols[0].1.handshake, None);
+        assert!(report.protocols[1].1.handshake.is_some());
+    }
+
+    #[tokio::test]
+    async fn checker_auth_not_asked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let page = judge_page(&read_head(&mut socket).await);
+            socket.write_all(page.as_bytes()).await.unwrap();
+        });
+        let checker = Checker::new(JUDGE.parse().unwrap())
+            .real_ip("203.0.113.7")
+            .protocols(&[Protocol::Http]);
+        let proxy: Uri = format!("http://user:pass@{}", addr).parse().unwrap();
+        let report = checker.check(&proxy).await;
+        server.await.unwrap();
+
+        assert!(report.supports(Protocol::Http));
+        assert_eq!(report.auth, Some(AuthStatus::NotRequired));
+        assert_eq!(report.anonymity, Some(Anonymity::Elite));
+    }
+
+    #[tokio::test]
+    async fn checker_auth_required() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 2));
+        let checker = Checker::new(JUDGE.parse().unwrap())
+            .real_ip("203.0.113.7")
+            .protocols(&[Protocol::Http, Protocol::Connect]);
+        let proxy: Uri = format!("http://{}", addr).parse().unwrap();
+        let report = checker.check(&proxy).await;
+        server.await.unwrap();
+
+        assert!(report.reachable && !report.is_working());
+        assert_eq!(report.auth, Some(AuthStatus::Required));
+        assert_eq!(report.anonymity, None);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: Uri = format!("socks5://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        drop(listener);
+        let report = checker.protocols(&[Protocol::Socks5]).check(&proxy).await;
+        assert!(!report.reachable);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.auth, None);
+    }
+}
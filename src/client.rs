@@ -1,9 +1,17 @@
-use bytes::Bytes;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
 use uri::Uri;
 
+use crate::auth::{proxy_auth_required, Authenticator, Target};
 use crate::client_builder::ClientBuilder;
+use crate::cookie::CookieStore;
+use crate::decoder::ResponseDecoder;
+use crate::decompress::decompress;
+use crate::encoder::RequestEncoder;
 use crate::error::Result;
 use crate::headers::Headers;
+use crate::multipart::Multipart;
 use crate::request::Request;
 use crate::response::Response;
 use crate::stream::MaybeHttpsStream;
@@ -13,8 +21,14 @@ pub struct Client {
     request: Request,
     uri: Uri,
     proxy: Option<Uri>,
+    via: Vec<Uri>,
     stream: MaybeHttpsStream,
     response: Option<Response>,
+    decompress: bool,
+    compression: Option<(String, usize)>,
+    cookie_store: Option<Arc<dyn CookieStore>>,
+    auth: Option<Arc<dyn Authenticator>>,
+    proxy_auth: Option<Arc<dyn Authenticator>>,
 }
 
 impl Client {
@@ -33,14 +47,162 @@ impl Client {
             request,
             uri,
             proxy,
+            via: Vec::new(),
             stream,
             response,
+            decompress: false,
+            compression: None,
+            cookie_store: None,
+            auth: None,
+            proxy_auth: None,
         }
     }
 
+    /// Decodes response bodies as `Content-Encoding` says.
+    pub(crate) fn decompress(mut self, decompress: bool) -> Client {
+        self.decompress = decompress;
+        self
+    }
+
+    /// `Content-Encoding` and minimum size of streamed request bodies to
+    /// compress.
+    pub(crate) fn compression(mut self, compression: Option<(String, usize)>) -> Client {
+        self.compression = compression;
+        self
+    }
+
+    /// Store sending cookies with the request and keeping those of the
+    /// response.
+    pub(crate) fn cookie_store(mut self, cookie_store: Option<Arc<dyn CookieStore>>) -> Client {
+        self.cookie_store = cookie_store;
+        self
+    }
+
+    /// Authenticators for the server and for an http proxy.
+    pub(crate) fn auth(
+        mut self,
+        auth: Option<Arc<dyn Authenticator>>,
+        proxy_auth: Option<Arc<dyn Authenticator>>,
+    ) -> Client {
+        self.auth = auth;
+        self.proxy_auth = proxy_auth;
+        self
+    }
+
+    /// Proxies before `proxy` in a chain, the connection is opened through
+    /// them.
+    pub(crate) fn via(mut self, via: Vec<Uri>) -> Client {
+        self.via = via;
+        self
+    }
+
+    /// Sends the request. When an authenticator takes up the challenge of a
+    /// 401 or 407 response, the request is sent once more on a new connection.
+    /// A 407 left unanswered fails with `Error::ProxyAuthRequired`.
     pub async fn send(&mut self) -> Result<Response> {
-        self.stream.send_msg(&self.request.to_vec()).await?;
-        let response = self.stream.get_response().await?;
+        let request = self.authorized(&self.request)?;
+        self.stream.send_msg(&request.to_vec()).await?;
+        let response = self.read_response().await?;
+        let (auth, target) = match u16::from(response.status_code()) {
+            401 => (self.auth.clone(), Target::Server),
+            407 => (self.proxy_auth.clone(), Target::Proxy),
+            _ => return Ok(response),
+        };
+        let response = match auth {
+            Some(auth) if auth.on_challenge(&mut self.request, &response, target)? => {
+                let request = self.authorized(&self.request)?;
+                let chain: Vec<Uri> = self.via.iter().chain(self.proxy.iter()).cloned().collect();
+                self.stream = MaybeHttpsStream::connect_chain(
+                    &self.uri,
+                    &chain,
+                    &[],
+                    self.proxy_auth.as_deref(),
+                )
+                .await?;
+                self.stream.send_msg(&request.to_vec()).await?;
+                self.read_response().await?
+            }
+            _ => response,
+        };
+        match u16::from(response.status_code()) {
+            407 => Err(proxy_auth_required(&response)),
+            _ => Ok(response),
+        }
+    }
+
+    /// Copy of `request` passed through the proxy then the server
+    /// authenticator, the last one sees the final headers. The proxy one only
+    /// runs when the proxy receives the request itself, a tunnelled request
+    /// never carries proxy credentials.
+    fn authorized(&self, request: &Request) -> Result<Request> {
+        let mut request = request.clone();
+        if let Some(auth) = self.proxy_auth.as_ref().filter(|_| request.forwarded()) {
+            auth.before_send(&mut request, Target::Proxy)?;
+        }
+        if let Some(auth) = &self.auth {
+            auth.before_send(&mut request, Target::Server)?;
+        }
+        Ok(request)
+    }
+
+    /// Sends the request with `form` as its body, streaming the parts. The
+    /// body is sent chunked when its length is not known in advance, or when
+    /// it is compressed on the way, which happens with `ClientBuilder::compress`
+    /// unless its known length is below the minimum size. The parts cannot be
+    /// read again to answer a challenge, a 407 fails with
+    /// `Error::ProxyAuthRequired`.
+    pub async fn send_multipart(&mut self, mut form: Multipart) -> Result<Response> {
+        let mut request = self.authorized(&self.request)?;
+        request.opt_body(None::<Bytes>);
+        request.header("Content-Type", &form.content_type());
+        match form.content_length() {
+            Some(len) => request
+                .header_remove("Transfer-Encoding")
+                .header("Content-Length", &len),
+            None => request
+                .header_remove("Content-Length")
+                .header("Transfer-Encoding", "chunked"),
+        };
+        let mut compressor = match &self.compression {
+            Some((content_encoding, min_size)) if !matches!(form.content_length(), Some(len) if len < *min_size as u64) => {
+                Some(request.compress_stream(content_encoding)?)
+            }
+            _ => None,
+        };
+        let mut encoder = RequestEncoder::new();
+        let mut buf = BytesMut::new();
+        encoder.encode_head(&request, &mut buf);
+        while let Some(chunk) = form.next_chunk().await? {
+            let chunk = match &mut compressor {
+                Some(compressor) => compressor.feed(&chunk)?,
+                None => chunk,
+            };
+            if !chunk.is_empty() {
+                encoder.encode_body(&chunk, &mut buf);
+                self.stream.send_msg(&buf.split()).await?;
+            }
+        }
+        if let Some(compressor) = compressor {
+            encoder.encode_body(&compressor.finish()?, &mut buf);
+        }
+        encoder.encode_end(&mut buf);
+        self.stream.send_msg(&buf).await?;
+        let response = self.read_response().await?;
+        match u16::from(response.status_code()) {
+            407 => Err(proxy_auth_required(&response)),
+            _ => Ok(response),
+        }
+    }
+
+    async fn read_response(&mut self) -> Result<Response> {
+        let decoder = ResponseDecoder::with_method(&self.request.get_method());
+        let mut response = self.stream.read_response(decoder).await?;
+        if self.decompress {
+            decompress(&mut response)?;
+        }
+        if let Some(store) = &self.cookie_store {
+            store.set_cookies(&self.uri, &response.headers().get_all("Set-Cookie"));
+        }
         self.response = Some(response.clone());
         Ok(response)
     }
@@ -69,6 +231,7 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::Error;
     use dotenv;
 
     const SIMPLE_URL: &'static str = "http://api.ipify.org";
@@ -145,8 +308,10 @@ mod tests {
             .build()
             .await
             .unwrap();
-        let response = client.send().await.unwrap();
-        assert!(response.status_code().is_client_err());
+        assert!(matches!(
+            client.send().await,
+            Err(Error::ProxyAuthRequired { .. })
+        ));
     }
 
     #[tokio::test]
@@ -199,7 +364,12 @@ mod tests {
             .proxy(&socks5_auth_proxy)
             .build()
             .await;
-        assert!(client.is_err());
+        assert!(matches!(
+            client,
+            Err(Error::Socks5Negotiation(
+                crate::socks5::Negotiation::AuthRejected(_)
+            ))
+        ));
     }
 
     #[test]
@@ -208,6 +378,577 @@ mod tests {
         assert_eq!(client_builder, ClientBuilder::new());
     }
 
+    #[tokio::test]
+    async fn client_http_proxy_tunnel() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut header = Vec::new();
+            while !header.ends_with(b"\r\n\r\n") {
+                header.push(socket.read_u8().await.unwrap());
+            }
+            socket
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(header).unwrap()
+        });
+
+        let result = Client::builder()
+            .get("https://example.com/ip")
+            .proxy(format!("http://{}", addr).as_str())
+            .build()
+            .await;
+        assert!(matches!(result, Err(Error::TunnelFailed(403))));
+        let header = server.await.unwrap();
+        assert!(header.starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn client_decompression() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\nContent-Length: 5\r\n\r\nhello")
+                    .await
+                    .unwrap();
+            }
+        });
+        let url = format!("http://{}/", addr);
+
+        let mut client = Client::builder().get(&url).build().await.unwrap();
+        let response = client.send().await.unwrap();
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.content_encoding(), Some("identity".to_string()));
+        assert_eq!(response.text().unwrap(), "hello");
+
+        let mut client = Client::builder()
+            .get(&url)
+            .no_decompression()
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.headers().get("Accept-Encoding"), None);
+        let response = client.send().await.unwrap();
+        assert_eq!(
+            response.headers().get("Content-Encoding"),
+            Some("identity".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn client_multipart() {
+        use crate::multipart::Part;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"0\r\n\r\n") {
+                request.push(socket.read_u8().await.unwrap());
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let mut client = Client::builder()
+            .post(format!("http://{}/", addr))
+            .build()
+            .await
+            .unwrap();
+        let form = Multipart::with_boundary("xyz").part("data", Part::reader(&b"hello"[..], None));
+        let response = client.send_multipart(form).await.unwrap();
+        assert!(response.status_code().is_success());
+
+        let request = server.await.unwrap();
+        assert!(request.contains("content-type: multipart/form-data; boundary=xyz\r\n"));
+        assert!(request.contains("transfer-encoding: chunked\r\n"));
+        assert!(request.contains("5\r\nhello\r\n"));
+    }
+
+    #[tokio::test]
+    async fn client_multipart_proxy_auth() {
+        use crate::multipart::Part;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"0\r\n\r\n") {
+                request.push(socket.read_u8().await.unwrap());
+            }
+            socket
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let mut client = Client::builder()
+            .post("http://example.com/")
+            .proxy(format!("http://{}", addr).as_str())
+            .build()
+            .await
+            .unwrap();
+        let form = Multipart::new().part("data", Part::reader(&b"hello"[..], None));
+        assert_eq!(
+            client.send_multipart(form).await.unwrap_err(),
+            Error::ProxyAuthRequired {
+                scheme: "Basic".to_string(),
+                realm: Some("proxy".to_string()),
+            }
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn client_multipart_compressed() {
+        use crate::decoder::{Event, RequestDecoder};
+        use crate::multipart::Part;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut decoder = RequestDecoder::new();
+            let mut head = None;
+            let mut body = Vec::new();
+            let mut buf = [0u8; 1024];
+            loop {
+                match decoder.decode().unwrap() {
+                    Some(Event::Head(request)) => head = Some(request),
+                    Some(Event::Body(chunk)) => body.extend_from_slice(&chunk),
+                    Some(Event::End) => break,
+                    None => {
+                        let n = socket.read(&mut buf).await.unwrap();
+                        decoder.feed(&buf[..n]);
+                    }
+                }
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            (head.unwrap().get_headers(), body)
+        });
+
+        let mut client = Client::builder()
+            .post(format!("http://{}/", addr))
+            .compress("gzip", 16)
+            .build()
+            .await
+            .unwrap();
+        let text = "hello ".repeat(100);
+        let form = Multipart::with_boundary("xyz").part("data", Part::text(&text));
+        let response = client.send_multipart(form).await.unwrap();
+        assert!(response.status_code().is_success());
+
+        let (headers, body) = server.await.unwrap();
+        assert_eq!(headers.get("Content-Encoding"), Some("gzip".to_string()));
+        assert_eq!(
+            headers.get("Transfer-Encoding"),
+            Some("chunked".to_string())
+        );
+        assert_eq!(headers.get("Content-Length"), None);
+        let body = crate::decompress::decode("gzip", &body).unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains(&text));
+    }
+
+    #[tokio::test]
+    async fn client_cookie_store() {
+        use crate::cookie::Jar;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2; HttpOnly\r\nContent-Length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+        let jar = Arc::new(Jar::new());
+        let url = format!("http://{}/", addr);
+
+        for _ in 0..2 {
+            let mut client = Client::builder()
+                .get(&url)
+                .cookie_store(jar.clone())
+                .build()
+                .await
+                .unwrap();
+            client.send().await.unwrap();
+        }
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("cookie:"));
+        assert!(requests[1].contains("cookie: a=1; b=2\r\n"));
+    }
+
+    #[tokio::test]
+    async fn client_digest_auth() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Digest realm=\"test\", qop=\"auth\", nonce=\"abc\", algorithm=SHA-256\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            ]
+            .iter()
+            {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+
+        let mut client = Client::builder()
+            .get(format!("http://{}/secret", addr))
+            .digest_auth("user", "pass")
+            .build()
+            .await
+            .unwrap();
+        let response = client.send().await.unwrap();
+        assert!(response.status_code().is_success());
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("authorization:"));
+        assert!(requests[1].contains(
+            "authorization: Digest username=\"user\", realm=\"test\", uri=\"/secret\", algorithm=SHA-256, nonce=\"abc\", qop=auth, nc=00000001,"
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_proxy_challenge() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\nContent-Length: 0\r\n\r\n",
+            ]
+            .iter()
+            {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+
+        let mut client = Client::builder()
+            .get("http://api.ipify.org/")
+            .proxy(format!("http://user:pass@{}", addr))
+            .build()
+            .await
+            .unwrap();
+        let response = client.send().await.unwrap();
+        assert!(response.status_code().is_success());
+
+        let mut client = Client::builder()
+            .get("http://api.ipify.org/")
+            .proxy(format!("http://{}", addr))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            client.send().await.unwrap_err(),
+            Error::ProxyAuthRequired {
+                scheme: "Basic".to_string(),
+                realm: Some("proxy".to_string()),
+            }
+        );
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("proxy-authorization:"));
+        assert!(requests[1].contains("proxy-authorization: Basic dXNlcjpwYXNz\r\n"));
+        assert!(!requests[2].contains("proxy-authorization:"));
+    }
+
+    #[tokio::test]
+    async fn client_no_local_dns() {
+        let client = Client::builder()
+            .get(SIMPLE_URL)
+            .proxy("socks5://127.0.0.1:1080")
+            .no_local_dns()
+            .build()
+            .await;
+        assert_eq!(
+            client.unwrap_err(),
+            Error::LocalDns("api.ipify.org".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn client_proxy_chain() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        async fn read_head(socket: &mut TcpStream) -> String {
+            let mut header = Vec::new();
+            while !header.ends_with(b"\r\n\r\n") {
+                header.push(socket.read_u8().await.unwrap());
+            }
+            String::from_utf8(header).unwrap()
+        }
+
+        // http proxy opening CONNECT tunnels
+        let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http.local_addr().unwrap();
+        let http_proxy = tokio::spawn(async move {
+            let (mut socket, _) = http.accept().await.unwrap();
+            let head = read_head(&mut socket).await;
+            let target = head.split(' ').nth(1).unwrap().to_string();
+            let mut upstream = TcpStream::connect(&target).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            tokio::io::copy_bidirectional(&mut socket, &mut upstream)
+                .await
+                .ok();
+            head
+        });
+
+        // socks5h proxy answering as the origin itself
+        let socks = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socks_addr = socks.local_addr().unwrap();
+        let socks_proxy = tokio::spawn(async move {
+            let (mut socket, _) = socks.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            socket.read_exact(&mut greeting).await.unwrap();
+            socket.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 5];
+            socket.read_exact(&mut request).await.unwrap();
+            let mut host = vec![0u8; request[4] as usize + 2];
+            socket.read_exact(&mut host).await.unwrap();
+            socket
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80])
+                .await
+                .unwrap();
+            let head = read_head(&mut socket).await;
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            (
+                String::from_utf8_lossy(&host[..host.len() - 2]).to_string(),
+                head,
+            )
+        });
+
+        let mut client = Client::builder()
+            .get("http://origin.invalid/path")
+            .proxy_chain(vec![
+                format!("http://{}", http_addr),
+                format!("socks5h://{}", socks_addr),
+            ])
+            .no_local_dns()
+            .build()
+            .await
+            .unwrap();
+        let response = client.send().await.unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+        drop(client);
+
+        let (host, head) = socks_proxy.await.unwrap();
+        assert_eq!(host, "origin.invalid");
+        assert!(head.starts_with("GET http://origin.invalid:80/path HTTP/1.1\r\n"));
+        let head = http_proxy.await.unwrap();
+        assert!(head.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", socks_addr)));
+
+        let client = Client::builder()
+            .get("http://origin.invalid/path")
+            .proxy_chain(vec![
+                format!("http://{}", socks_addr),
+                format!("socks5h://{}", socks_addr),
+            ])
+            .build()
+            .await;
+        assert!(matches!(client, Err(Error::ProxyHop(1, _))));
+    }
+
+    #[tokio::test]
+    async fn client_proxy_chain_errors() {
+        use crate::socks5::Reply;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let client = Client::builder()
+            .get("http://origin.invalid/path")
+            .proxy_chain(vec!["http://127.0.0.1:8080", "socks5h://127.0.0.1:port"])
+            .build()
+            .await;
+        assert!(matches!(
+            client,
+            Err(Error::ProxyHop(2, err)) if matches!(*err, Error::UriError(_))
+        ));
+
+        // http proxy opening CONNECT tunnels
+        let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = http.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(socket.read_u8().await.unwrap());
+            }
+            let head = String::from_utf8(head).unwrap();
+            let target = head.split(' ').nth(1).unwrap().to_string();
+            let mut upstream = TcpStream::connect(&target).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+            tokio::io::copy_bidirectional(&mut socket, &mut upstream)
+                .await
+                .ok();
+        });
+
+        // socks5h proxy refusing the connection to the origin
+        let socks = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socks_addr = socks.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = socks.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            socket.read_exact(&mut greeting).await.unwrap();
+            socket.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 5];
+            socket.read_exact(&mut request).await.unwrap();
+            let mut host = vec![0u8; request[4] as usize + 2];
+            socket.read_exact(&mut host).await.unwrap();
+            socket
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        let client = Client::builder()
+            .get("http://origin.invalid/path")
+            .proxy_chain(vec![
+                format!("http://{}", http_addr),
+                format!("socks5h://{}", socks_addr),
+            ])
+            .build()
+            .await;
+        assert_eq!(
+            client.unwrap_err(),
+            Error::ProxyHop(2, Box::new(Error::Socks5(Reply::ConnectionRefused)))
+        );
+    }
+
+    #[tokio::test]
+    async fn client_authenticator() {
+        use crate::auth::{Authenticator, Target};
+        use sha2::{Digest, Sha256};
+        use std::sync::Mutex;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        /// Signs the request and renews an expired token on a 401.
+        #[derive(Debug)]
+        struct Signer(Mutex<String>);
+
+        impl Authenticator for Signer {
+            fn before_send(&self, request: &mut Request, target: Target) -> Result<()> {
+                let token = self.0.lock().unwrap().clone();
+                request.header(target.authorization(), &format!("Bearer {}", token));
+                let signature = Sha256::digest(&request.to_vec());
+                request.header("X-Signature", &format!("{:x}", signature));
+                Ok(())
+            }
+
+            fn on_challenge(&self, _: &mut Request, _: &Response, _: Target) -> Result<bool> {
+                *self.0.lock().unwrap() = "fresh".to_string();
+                Ok(true)
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["401 Unauthorized", "200 OK"].iter() {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+
+        let mut client = Client::builder()
+            .get(format!("http://{}/", addr))
+            .auth(Arc::new(Signer(Mutex::new("expired".to_string()))))
+            .build()
+            .await
+            .unwrap();
+        let response = client.send().await.unwrap();
+        assert!(response.status_code().is_success());
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("authorization: Bearer expired\r\n"));
+        assert!(requests[1].contains("authorization: Bearer fresh\r\n"));
+        assert!(requests[1].contains("x-signature: "));
+    }
+
     #[tokio::test]
     async fn client_content_len() {
         let client = Client::builder().build().await;
@@ -1,14 +1,24 @@
-use std::{convert::TryInto, time::Duration};
+use std::{convert::TryInto, sync::Arc, time::Duration};
 
 use bytes::Bytes;
+#[cfg(feature = "serde")]
+use serde_crate::Serialize;
 use uri::Uri;
 
+use crate::auth::{Authenticator, Credentials, Digest, SharedAuth};
 use crate::client::Client;
+use crate::cookie::{CookieStore, SharedStore};
+use crate::decompress::accept_encoding;
 use crate::error::{Error, Result};
+use crate::form::{append_query, encode_pairs};
 use crate::headers::Headers;
+#[cfg(feature = "serde")]
+use crate::json::json_error;
 use crate::method::Method;
+use crate::proxy_rule::ProxyRule;
 use crate::request::Request;
-use crate::stream::MaybeHttpsStream;
+use crate::route::Routes;
+use crate::stream::{check_no_local_dns, MaybeHttpsStream};
 use crate::version::Version;
 
 #[derive(Debug, PartialEq)]
@@ -19,9 +29,21 @@ pub struct ClientBuilder {
     version: Version,
     body: Option<Bytes>,
     proxy: Option<Uri>,
+    via: Vec<Uri>,
+    proxy_rule: Option<ProxyRule>,
+    routes: Option<Routes>,
     nodelay: bool,
     timeout: Option<Duration>,
     connect_timeout: Option<Duration>,
+    decompress: bool,
+    compression: Option<(String, usize)>,
+    query: String,
+    cookie_store: Option<SharedStore>,
+    auth: Option<SharedAuth>,
+    proxy_auth: Option<SharedAuth>,
+    preemptive_proxy_auth: bool,
+    no_local_dns: bool,
+    error: Option<Error>,
 }
 
 impl Default for ClientBuilder {
@@ -40,34 +62,91 @@ impl ClientBuilder {
             version: Version::Http11,
             body: None,
             proxy: None,
+            via: Vec::new(),
+            proxy_rule: None,
+            routes: None,
             nodelay: false,
             timeout: None,
             connect_timeout: None,
+            decompress: true,
+            compression: None,
+            query: String::new(),
+            cookie_store: None,
+            auth: None,
+            proxy_auth: None,
+            preemptive_proxy_auth: false,
+            no_local_dns: false,
+            error: None,
         }
     }
 
-    pub async fn build(self) -> Result<Client> {
-        let uri = self.uri.ok_or(Error::EmptyUri)?;
-        let mut headers = self.headers;
-        let stream = match &self.proxy {
-            Some(proxy) => match proxy.scheme() {
-                "socks5" | "socks5h" => Ok(MaybeHttpsStream::socks(&proxy, &uri).await?),
-                "http" | "https" => {
-                    if let Some(auth) = proxy.base64_auth() {
-                        headers.insert("Proxy-Authorization", format!("Basic {}", auth).as_str());
-                    };
-                    Ok(MaybeHttpsStream::new(&proxy).await?)
-                }
-                scheme => Err(Error::UnsupportedProxyScheme(scheme.to_owned())),
-            },
-            None => Ok(MaybeHttpsStream::new(&uri).await?),
-        }?;
+    /// Builds the client, failing with the first error a setter ran into.
+    pub async fn build(mut self) -> Result<Client> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let uri = append_query(&self.uri.ok_or(Error::EmptyUri)?, &self.query)?;
+        if self.proxy.is_none() {
+            self.proxy = match (&self.routes, &self.proxy_rule) {
+                (Some(routes), _) => routes.select(&uri),
+                (None, Some(rule)) => rule.select(&uri),
+                (None, None) => None,
+            };
+        }
+        let chain: Vec<Uri> = self.via.iter().chain(self.proxy.iter()).cloned().collect();
+        if self.no_local_dns {
+            for (hop, proxy) in chain.iter().enumerate() {
+                check_no_local_dns(chain.get(hop + 1).unwrap_or(&uri), Some(proxy))?;
+            }
+        }
+        let proxy_auth = match self.proxy_auth {
+            Some(auth) => Some(auth.0),
+            None => {
+                let preemptive = self.preemptive_proxy_auth;
+                self.proxy
+                    .as_ref()
+                    .filter(|proxy| proxy.scheme().starts_with("http"))
+                    .and_then(Credentials::from_uri)
+                    .map(|credentials| {
+                        if preemptive {
+                            credentials.preemptive()
+                        } else {
+                            credentials
+                        }
+                    })
+                    .map(|credentials| Arc::new(credentials) as Arc<dyn Authenticator>)
+            }
+        };
         let mut request = Request::new(&uri, self.proxy.as_ref());
         request.method(self.method);
+        let mut headers = self.headers;
+        let cookie_store = self.cookie_store.map(|store| store.0);
+        if let Some(cookies) = cookie_store.as_ref().and_then(|store| store.cookies(&uri)) {
+            let cookies = match headers.get("Cookie") {
+                Some(own) => format!("{}; {}", own, cookies),
+                None => cookies,
+            };
+            headers.insert("Cookie", &cookies);
+        }
+        if self.decompress && headers.get("Accept-Encoding").is_none() {
+            if let Some(accept) = accept_encoding() {
+                headers.insert("Accept-Encoding", &accept);
+            }
+        }
         request.headers(headers);
         request.version(self.version);
         request.opt_body(self.body);
-        Ok(Client::new(request, uri, self.proxy, stream, None))
+        if let Some((content_encoding, min_size)) = &self.compression {
+            request.compress(content_encoding, *min_size)?;
+        }
+        let stream =
+            MaybeHttpsStream::connect_chain(&uri, &chain, &[], proxy_auth.as_deref()).await?;
+        Ok(Client::new(request, uri, self.proxy, stream, None)
+            .decompress(self.decompress)
+            .compression(self.compression)
+            .cookie_store(cookie_store)
+            .auth(self.auth.map(|auth| auth.0), proxy_auth)
+            .via(self.via))
     }
 
     pub fn uri<U>(mut self, value: U) -> ClientBuilder
@@ -81,6 +160,8 @@ impl ClientBuilder {
         self
     }
 
+    /// Goes out through `value`. An `https` target behind an http proxy is
+    /// reached through a CONNECT tunnel, the proxy only sees its host and port.
     pub fn proxy<P>(mut self, value: P) -> ClientBuilder
     where
         P: TryInto<Uri>,
@@ -92,6 +173,52 @@ impl ClientBuilder {
         self
     }
 
+    /// Picks the proxy for the target with `routes` when none is set with
+    /// `proxy` or `proxy_chain`. It takes precedence over `proxy_rule`.
+    pub fn routes(mut self, routes: Routes) -> ClientBuilder {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Picks the proxy for the target with `rule` when none is set with
+    /// `proxy` or `proxy_chain`.
+    pub fn proxy_rule(mut self, rule: ProxyRule) -> ClientBuilder {
+        self.proxy_rule = Some(rule);
+        self
+    }
+
+    /// Picks the proxy from `http_proxy`, `https_proxy`, `all_proxy` and
+    /// `no_proxy` as curl does, see `ProxyRule::from_env`. A value that does
+    /// not parse fails `build` with `Error::ProxyEnv`.
+    pub fn env_proxy(self) -> ClientBuilder {
+        match ProxyRule::from_env() {
+            Ok(rule) => self.proxy_rule(rule),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Goes out through `chain` in order, each proxy tunnelled through the
+    /// ones before it and answered with the credentials of its own URI. The
+    /// last one is used as `proxy` would be. An invalid URI fails `build`
+    /// with `Error::ProxyHop` holding its parse error, hops counted from 1.
+    pub fn proxy_chain<I, P>(mut self, chain: I) -> ClientBuilder
+    where
+        I: IntoIterator<Item = P>,
+        P: TryInto<Uri>,
+        Error: From<P::Error>,
+    {
+        let mut uris = Vec::new();
+        for (hop, proxy) in chain.into_iter().enumerate() {
+            match proxy.try_into() {
+                Ok(uri) => uris.push(uri),
+                Err(err) => return self.fail(Error::ProxyHop(hop + 1, Box::new(err.into()))),
+            }
+        }
+        self.proxy = uris.pop();
+        self.via = uris;
+        self
+    }
+
     pub fn headers(mut self, headers: Headers) -> ClientBuilder {
         for (key, value) in headers.iter() {
             self.headers.insert(key, &value);
@@ -197,7 +324,8 @@ impl ClientBuilder {
         }
     }
 
-    pub fn json<B>(mut self, value: B) -> ClientBuilder
+    /// Sets a body that is already JSON encoded.
+    pub fn json_bytes<B>(mut self, value: B) -> ClientBuilder
     where
         B: TryInto<Bytes>,
     {
@@ -210,11 +338,134 @@ impl ClientBuilder {
         }
     }
 
+    /// Serializes `value` as the JSON body. A value that cannot be serialized
+    /// fails `build` with `Error::Json`.
+    #[cfg(feature = "serde")]
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> ClientBuilder {
+        match serde_json::to_vec(value) {
+            Ok(body) => self.json_bytes(body),
+            Err(err) => self.fail(json_error(err, &[])),
+        }
+    }
+
+    /// Sets an `application/x-www-form-urlencoded` body built from `pairs`.
+    pub fn form<K, V>(self, pairs: &[(K, V)]) -> ClientBuilder
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.body(encode_pairs(pairs))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+    }
+
+    /// Serializes `value` as an `application/x-www-form-urlencoded` body. A
+    /// value that cannot be serialized fails `build` with `Error::Form`.
+    #[cfg(feature = "serde")]
+    pub fn form_serde<T: Serialize + ?Sized>(self, value: &T) -> ClientBuilder {
+        match serde_urlencoded::to_string(value) {
+            Ok(body) => self
+                .body(body)
+                .header("Content-Type", "application/x-www-form-urlencoded"),
+            Err(err) => self.fail(Error::Form(err.to_string())),
+        }
+    }
+
+    /// Adds `pairs` to the query string of the URI, the calls accumulate.
+    pub fn query<K, V>(self, pairs: &[(K, V)]) -> ClientBuilder
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let query = encode_pairs(pairs);
+        self.push_query(&query)
+    }
+
+    /// Serializes `value` into the query string of the URI. A value that
+    /// cannot be serialized fails `build` with `Error::Form`.
+    #[cfg(feature = "serde")]
+    pub fn query_serde<T: Serialize + ?Sized>(self, value: &T) -> ClientBuilder {
+        match serde_urlencoded::to_string(value) {
+            Ok(query) => self.push_query(&query),
+            Err(err) => self.fail(Error::Form(err.to_string())),
+        }
+    }
+
+    fn push_query(mut self, query: &str) -> ClientBuilder {
+        if !query.is_empty() {
+            if !self.query.is_empty() {
+                self.query.push('&');
+            }
+            self.query.push_str(query);
+        }
+        self
+    }
+
+    /// Keeps `err` to be returned by `build`, the first one wins.
+    pub(crate) fn fail(mut self, err: Error) -> ClientBuilder {
+        self.error.get_or_insert(err);
+        self
+    }
+
     pub fn tcp_nodelay(mut self) -> ClientBuilder {
         self.nodelay = true;
         self
     }
 
+    /// Fails `build` with `Error::LocalDns` rather than resolving the target
+    /// host name here while a proxy is configured. Use `socks5h`, `socks4a`
+    /// or an http proxy so the proxy resolves it.
+    pub fn no_local_dns(mut self) -> ClientBuilder {
+        self.no_local_dns = true;
+        self
+    }
+
+    /// Leaves response bodies as sent and does not add `Accept-Encoding`.
+    pub fn no_decompression(mut self) -> ClientBuilder {
+        self.decompress = false;
+        self
+    }
+
+    /// Compresses the request body with `content_encoding` when it is at
+    /// least `min_size` bytes long. Streamed multipart bodies are compressed
+    /// chunk by chunk and sent chunked.
+    pub fn compress(mut self, content_encoding: &str, min_size: usize) -> ClientBuilder {
+        self.compression = Some((content_encoding.to_string(), min_size));
+        self
+    }
+
+    /// Sends the matching cookies of `store` and keeps the `Set-Cookie`
+    /// values of the response in it.
+    pub fn cookie_store(mut self, store: Arc<dyn CookieStore>) -> ClientBuilder {
+        self.cookie_store = Some(SharedStore(store));
+        self
+    }
+
+    /// Authenticates requests to the server with `auth`.
+    pub fn auth(mut self, auth: Arc<dyn Authenticator>) -> ClientBuilder {
+        self.auth = Some(SharedAuth(auth));
+        self
+    }
+
+    /// Authenticates to an http proxy with `auth`. Without it, a proxy given
+    /// with credentials in its URI gets them once it answers 407, as `Digest`
+    /// when offered and `Basic` otherwise.
+    pub fn proxy_auth(mut self, auth: Arc<dyn Authenticator>) -> ClientBuilder {
+        self.proxy_auth = Some(SharedAuth(auth));
+        self
+    }
+
+    /// Sends the credentials of the proxy URI as `Basic` before any 407,
+    /// saving a round trip with proxies known to want them.
+    pub fn preemptive_proxy_auth(mut self) -> ClientBuilder {
+        self.preemptive_proxy_auth = true;
+        self
+    }
+
+    /// Answers a `Digest` challenge of the server with these credentials.
+    pub fn digest_auth(self, username: &str, password: &str) -> ClientBuilder {
+        self.auth(Arc::new(Digest::new(username, password)))
+    }
+
     pub fn timeout(mut self, timeout: Duration) -> ClientBuilder {
         self.timeout = Some(timeout);
         self
@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::decoder::{Event, RequestDecoder, ResponseDecoder};
+use crate::encoder::{RequestEncoder, ResponseEncoder};
+use crate::error::{Error, Result};
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Client side of `tokio_util::codec::Framed`: encodes `Request`s and decodes
+/// complete `Response`s. Methods of sent requests are queued so that responses
+/// to HEAD are framed without a body.
+#[derive(Debug, Default)]
+pub struct ClientCodec {
+    decoder: Option<ResponseDecoder>,
+    methods: VecDeque<Method>,
+    response: Option<Response>,
+    body: BytesMut,
+}
+
+impl ClientCodec {
+    pub fn new() -> ClientCodec {
+        ClientCodec::default()
+    }
+
+    fn decoder(&mut self) -> &mut ResponseDecoder {
+        let method = self.methods.front().cloned().unwrap_or_default();
+        self.decoder
+            .get_or_insert_with(|| ResponseDecoder::with_method(&method))
+    }
+
+    fn poll(&mut self) -> Result<Option<Response>> {
+        while let Some(event) = self.decoder().decode()? {
+            match event {
+                Event::Head(response) => self.response = Some(response),
+                Event::Body(chunk) => self.body.extend_from_slice(&chunk),
+                Event::End => {
+                    let mut response = self.response.take().ok_or(Error::EmptyResponse)?;
+                    response.body = self.body.split().freeze();
+                    let rest = self.decoder().remaining().to_vec();
+                    self.decoder = None;
+                    self.methods.pop_front();
+                    self.decoder().feed(&rest);
+                    return Ok(Some(response));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_idle(&self) -> bool {
+        let buffered = match &self.decoder {
+            Some(decoder) => !decoder.remaining().is_empty(),
+            None => false,
+        };
+        self.response.is_none() && !buffered
+    }
+}
+
+impl Encoder<Request> for ClientCodec {
+    type Error = Error;
+
+    fn encode(&mut self, request: Request, dst: &mut BytesMut) -> Result<()> {
+        RequestEncoder::new().encode(&request, dst);
+        self.methods.push_back(request.get_method());
+        Ok(())
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = Response;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Response>> {
+        if !src.is_empty() {
+            let data = src.split();
+            self.decoder().feed(&data);
+        }
+        self.poll()
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Response>> {
+        if let Some(response) = self.decode(src)? {
+            return Ok(Some(response));
+        }
+        if self.is_idle() {
+            return Ok(None);
+        }
+        self.decoder().finish();
+        self.poll()
+    }
+}
+
+/// Server side of `tokio_util::codec::Framed`: decodes complete `Request`s and
+/// encodes `Response`s. A chunked request body is collected and handed out
+/// with `Content-Length` in place of `Transfer-Encoding`.
+#[derive(Debug, Default)]
+pub struct ServerCodec {
+    decoder: RequestDecoder,
+    request: Option<Request>,
+    body: BytesMut,
+}
+
+impl ServerCodec {
+    pub fn new() -> ServerCodec {
+        ServerCodec::default()
+    }
+
+    fn poll(&mut self) -> Result<Option<Request>> {
+        while let Some(event) = self.decoder.decode()? {
+            match event {
+                Event::Head(request) => self.request = Some(request),
+                Event::Body(chunk) => self.body.extend_from_slice(&chunk),
+                Event::End => {
+                    let mut request = self.request.take().ok_or(Error::EmptyRequest)?;
+                    if !self.body.is_empty() {
+                        request.header_remove("Transfer-Encoding");
+                        request.body(self.body.split().freeze());
+                    }
+                    let rest = self.decoder.remaining().to_vec();
+                    self.decoder = RequestDecoder::new();
+                    self.decoder.feed(&rest);
+                    return Ok(Some(request));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<Response> for ServerCodec {
+    type Error = Error;
+
+    fn encode(&mut self, response: Response, dst: &mut BytesMut) -> Result<()> {
+        ResponseEncoder::new().encode(&response, dst);
+        Ok(())
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = Request;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Request>> {
+        if !src.is_empty() {
+            let data = src.split();
+            self.decoder.feed(&data);
+        }
+        self.poll()
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Request>> {
+        if let Some(request) = self.decode(src)? {
+            return Ok(Some(request));
+        }
+        if self.request.is_none() && self.decoder.remaining().is_empty() {
+            return Ok(None);
+        }
+        self.decoder.finish();
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uri::Uri;
+
+    const RESPONSES: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                               Content-Length: 5\r\n\r\n\
+                               HTTP/1.1 200 OK\r\n\
+                               Transfer-Encoding: chunked\r\n\r\n\
+                               5\r\nhello\r\n0\r\n\r\n";
+
+    #[test]
+    fn client_codec_pipelined() {
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let mut codec = ClientCodec::new();
+        let mut dst = BytesMut::new();
+        let mut head = Request::new(&uri, None);
+        head.method(Method::HEAD);
+        codec.encode(head, &mut dst).unwrap();
+        codec.encode(Request::new(&uri, None), &mut dst).unwrap();
+
+        let mut src = BytesMut::from(RESPONSES);
+        let first = codec.decode(&mut src).unwrap().unwrap();
+        let second = codec.decode(&mut src).unwrap().unwrap();
+
+        assert!(first.body.is_empty());
+        assert_eq!(&second.body[..], b"hello");
+        assert_eq!(codec.decode(&mut src), Ok(None));
+        assert_eq!(codec.decode_eof(&mut src), Ok(None));
+    }
+
+    #[test]
+    fn client_codec_eof() {
+        let mut codec = ClientCodec::new();
+        let mut src = BytesMut::from(&b"HTTP/1.0 200 OK\r\n\r\nhello"[..]);
+
+        assert_eq!(codec.decode(&mut src), Ok(None));
+        let response = codec.decode_eof(&mut src).unwrap().unwrap();
+        assert_eq!(&response.body[..], b"hello");
+    }
+
+    #[test]
+    fn server_codec() {
+        let mut codec = ServerCodec::new();
+        let mut src = BytesMut::from(
+            &b"POST /path HTTP/1.1\r\n\
+               Host: api.ipify.org\r\n\
+               Transfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n0\r\n\r\n\
+               GET / HTTP/1.1\r\n\
+               Host: api.ipify.org\r\n\r\n"[..],
+        );
+        let post = codec.decode(&mut src).unwrap().unwrap();
+        let get = codec.decode(&mut src).unwrap().unwrap();
+
+        assert_eq!(post.get_method(), Method::POST);
+        assert_eq!(post.request_uri(), "/path");
+        assert_eq!(post.content_length(), 5);
+        assert_eq!(post.get_headers().get("Transfer-Encoding"), None);
+        assert_eq!(get.get_method(), Method::GET);
+        assert_eq!(get.get_body(), None);
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+
+        let mut response = Response::from_header(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        response.body = "hello".into();
+        let mut dst = BytesMut::new();
+        codec.encode(response.clone(), &mut dst).unwrap();
+
+        let mut client = ClientCodec::new();
+        let decoded = client.decode(&mut dst).unwrap().unwrap();
+        assert_eq!(decoded.body, response.body);
+    }
+}
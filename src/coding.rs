@@ -0,0 +1,105 @@
+use std::{fmt, io};
+
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "brotli",
+    feature = "zstd"
+))]
+use std::{io::Write, mem};
+
+use bytes::Bytes;
+
+use crate::error::Result;
+
+/// One coding of a `Content-Encoding` value, either applied or undone.
+pub(crate) trait Stage: Sized {
+    fn new(coding: &str) -> Result<Self>;
+
+    /// Passes `input` through, returning whatever output is already available.
+    fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Returns the output left once the input has ended.
+    fn finish(self) -> io::Result<Vec<u8>>;
+}
+
+/// Chain of stages a body goes through in order, shared by `Compressor` and
+/// `Decompressor`.
+pub(crate) struct Stages<S> {
+    content_encoding: String,
+    stages: Vec<S>,
+}
+
+impl<S: Stage> Stages<S> {
+    /// `codings` lists the codings of `content_encoding` in the order the
+    /// body goes through them.
+    pub(crate) fn new<'a, I>(content_encoding: &str, codings: I) -> Result<Stages<S>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let stages = codings
+            .map(|coding| S::new(&coding.trim().to_ascii_lowercase()))
+            .collect::<Result<Vec<S>>>()?;
+        Ok(Stages {
+            content_encoding: content_encoding.to_string(),
+            stages,
+        })
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Result<Bytes> {
+        let mut data = chunk.to_vec();
+        for stage in self.stages.iter_mut() {
+            if data.is_empty() {
+                break;
+            }
+            data = stage.write(&data)?;
+        }
+        Ok(data.into())
+    }
+
+    pub(crate) fn finish(self) -> Result<Bytes> {
+        let mut data = Vec::new();
+        for mut stage in self.stages {
+            let mut output = if data.is_empty() {
+                Vec::new()
+            } else {
+                stage.write(&data)?
+            };
+            output.extend(stage.finish()?);
+            data = output;
+        }
+        Ok(data.into())
+    }
+
+    pub(crate) fn fmt(&self, name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct(name)
+            .field("content_encoding", &self.content_encoding)
+            .finish()
+    }
+}
+
+/// Writes `input` to a coder writing into a `Vec` and takes what came out.
+/// Decoders are flushed so that every chunk yields its output, encoders are
+/// not: a flush ends the current block and would hurt the ratio.
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "brotli",
+    feature = "zstd"
+))]
+pub(crate) fn write_take<W, F>(
+    writer: &mut W,
+    input: &[u8],
+    flush: bool,
+    output: F,
+) -> io::Result<Vec<u8>>
+where
+    W: Write,
+    F: Fn(&mut W) -> &mut Vec<u8>,
+{
+    writer.write_all(input)?;
+    if flush {
+        writer.flush()?;
+    }
+    Ok(mem::take(output(writer)))
+}
@@ -0,0 +1,132 @@
+use std::{fmt, io};
+
+use bytes::Bytes;
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "zstd"))]
+use crate::coding::write_take;
+use crate::coding::{self, Stages};
+use crate::error::{Error, Result};
+
+/// Applies every coding listed in a `Content-Encoding` value, in order.
+pub fn encode(content_encoding: &str, body: &[u8]) -> Result<Bytes> {
+    let mut compressor = Compressor::new(content_encoding)?;
+    let mut encoded = compressor.feed(body)?.to_vec();
+    encoded.extend_from_slice(&compressor.finish()?);
+    Ok(encoded.into())
+}
+
+/// Streaming counterpart of `encode` for request bodies sent in chunks with
+/// `RequestEncoder::encode_body`.
+pub struct Compressor(Stages<Stage>);
+
+impl Compressor {
+    pub fn new(content_encoding: &str) -> Result<Compressor> {
+        Stages::new(content_encoding, content_encoding.split(',')).map(Compressor)
+    }
+
+    /// Compresses the next chunk of the body, returning whatever output is
+    /// already available.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Bytes> {
+        self.0.feed(chunk)
+    }
+
+    /// Writes the trailers of every coding.
+    pub fn finish(self) -> Result<Bytes> {
+        self.0.finish()
+    }
+}
+
+impl fmt::Debug for Compressor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt("Compressor", f)
+    }
+}
+
+enum Stage {
+    Identity,
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl coding::Stage for Stage {
+    fn new(coding: &str) -> Result<Stage> {
+        match coding {
+            "" | "identity" => Ok(Stage::Identity),
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => Ok(Stage::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "deflate")]
+            "deflate" => Ok(Stage::Deflate(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(Stage::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                0,
+            )?)),
+            coding => Err(Error::UnsupportedEncoding(coding.to_string())),
+        }
+    }
+
+    fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Stage::Identity => Ok(input.to_vec()),
+            #[cfg(feature = "gzip")]
+            Stage::Gzip(encoder) => write_take(encoder, input, false, |e| e.get_mut()),
+            #[cfg(feature = "deflate")]
+            Stage::Deflate(encoder) => write_take(encoder, input, false, |e| e.get_mut()),
+            #[cfg(feature = "zstd")]
+            Stage::Zstd(encoder) => write_take(encoder, input, false, |e| e.get_mut()),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Stage::Identity => Ok(Vec::new()),
+            #[cfg(feature = "gzip")]
+            Stage::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "deflate")]
+            Stage::Deflate(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Stage::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_identity() {
+        assert_eq!(encode("identity", b"hello"), Ok(Bytes::from("hello")));
+        assert_eq!(
+            encode("unknown", b"hello"),
+            Err(Error::UnsupportedEncoding("unknown".to_string()))
+        );
+    }
+
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn encode_round_trip() {
+        use crate::decompress::decode;
+
+        let text = "hello world ".repeat(100);
+        let mut compressor = Compressor::new("gzip, zstd").unwrap();
+        let mut body = Vec::new();
+        for chunk in text.as_bytes().chunks(7) {
+            body.extend_from_slice(&compressor.feed(chunk).unwrap());
+        }
+        body.extend_from_slice(&compressor.finish().unwrap());
+
+        assert!(body.len() < text.len());
+        assert_eq!(decode("gzip, zstd", &body), Ok(Bytes::from(text)));
+    }
+}
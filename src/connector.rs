@@ -0,0 +1,307 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http::header::{HeaderValue, PROXY_AUTHORIZATION};
+use hyper::client::connect::{Connected, Connection};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower_layer::Layer;
+use tower_service::Service;
+use uri::Uri;
+
+use crate::error::{Error, Result};
+use crate::stream::{check_no_local_dns, MaybeHttpsStream};
+
+/// Proxy-aware connector for hyper and other stacks built on
+/// `tower::Service<http::Uri>`. Connections are opened the same way as for
+/// `Client`: direct, through an http proxy or through a SOCKS proxy.
+///
+/// Plain `http` targets behind an http(s) proxy are forwarded, the client then
+/// needs `auth_layer` for the proxy credentials to be sent. `https` targets
+/// behind an http(s) proxy go through a CONNECT tunnel, so the proxy never
+/// sees the request.
+#[derive(Debug, Clone, Default)]
+pub struct Connector {
+    proxy: Option<Uri>,
+    alpn: Vec<Vec<u8>>,
+    no_local_dns: bool,
+}
+
+impl Connector {
+    pub fn new() -> Connector {
+        Connector::default()
+    }
+
+    pub fn proxy(mut self, proxy: Uri) -> Connector {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Fails with `Error::LocalDns` rather than resolving the target host
+    /// name here, as `socks4` and `socks5` proxies need.
+    pub fn no_local_dns(mut self) -> Connector {
+        self.no_local_dns = true;
+        self
+    }
+
+    pub fn alpn_protocols(mut self, protocols: &[&str]) -> Connector {
+        self.alpn = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    /// Layer for the client built on this connector, adding the credentials
+    /// of an http proxy URI as `Proxy-Authorization` to forwarded requests.
+    pub fn auth_layer(&self) -> ProxyAuthLayer {
+        let value = self
+            .proxy
+            .as_ref()
+            .filter(|proxy| matches!(proxy.scheme(), "http" | "https"))
+            .and_then(|proxy| proxy.base64_auth())
+            .and_then(|auth| HeaderValue::from_str(&format!("Basic {}", auth)).ok());
+        ProxyAuthLayer { value }
+    }
+}
+
+impl Service<http::Uri> for Connector {
+    type Response = ProxyStream;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ProxyStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: http::Uri) -> Self::Future {
+        let proxy = self.proxy.clone();
+        let alpn = self.alpn.clone();
+        let no_local_dns = self.no_local_dns;
+        Box::pin(async move {
+            let uri: Uri = dst.to_string().parse()?;
+            if no_local_dns {
+                check_no_local_dns(&uri, proxy.as_ref())?;
+            }
+            let proxied = match &proxy {
+                Some(proxy) => matches!(proxy.scheme(), "http" | "https") && !uri.is_ssl(),
+                None => false,
+            };
+            let stream = MaybeHttpsStream::connect_alpn(&uri, proxy.as_ref(), &alpn).await?;
+            Ok(ProxyStream { stream, proxied })
+        })
+    }
+}
+
+/// `MaybeHttpsStream` returned by `Connector`. It remembers whether requests
+/// on it must be sent in absolute form to an http proxy.
+#[derive(Debug)]
+pub struct ProxyStream {
+    stream: MaybeHttpsStream,
+    proxied: bool,
+}
+
+impl ProxyStream {
+    pub fn is_proxied(&self) -> bool {
+        self.proxied
+    }
+
+    pub fn get_ref(&self) -> &MaybeHttpsStream {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> MaybeHttpsStream {
+        self.stream
+    }
+}
+
+/// Adds the `Proxy-Authorization` of a `Connector` proxy to every request
+/// forwarded to it, as `Connector::auth_layer` gives it.
+#[derive(Debug, Clone)]
+pub struct ProxyAuthLayer {
+    value: Option<HeaderValue>,
+}
+
+impl<S> Layer<S> for ProxyAuthLayer {
+    type Service = ProxyAuth<S>;
+
+    fn layer(&self, inner: S) -> ProxyAuth<S> {
+        ProxyAuth {
+            inner,
+            value: self.value.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyAuth<S> {
+    inner: S,
+    value: Option<HeaderValue>,
+}
+
+impl<S, B> Service<http::Request<B>> for ProxyAuth<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> S::Future {
+        if let Some(value) = &self.value {
+            if request.uri().scheme_str() == Some("http") {
+                request
+                    .headers_mut()
+                    .entry(PROXY_AUTHORIZATION)
+                    .or_insert_with(|| value.clone());
+            }
+        }
+        self.inner.call(request)
+    }
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        self.stream.connected().proxy(self.proxied)
+    }
+}
+
+impl Connection for MaybeHttpsStream {
+    fn connected(&self) -> Connected {
+        match self.alpn_protocol() {
+            Some(protocol) if protocol == b"h2" => Connected::new().negotiated_h2(),
+            _ => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut Pin::get_mut(self).stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut Pin::get_mut(self).stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut Pin::get_mut(self).stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut Pin::get_mut(self).stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn connector_hyper() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut header = Vec::new();
+            while !header.ends_with(b"\r\n\r\n") {
+                header.push(socket.read_u8().await.unwrap());
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .await
+                .unwrap();
+        });
+
+        let client = hyper::Client::builder().build::<_, hyper::Body>(Connector::new());
+        let uri: http::Uri = format!("http://{}/", addr).parse().unwrap();
+        let response = client.get(uri).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn connector_proxied() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = listener.accept().await.unwrap();
+        });
+
+        let proxy: Uri = format!("http://{}", addr).parse().unwrap();
+        let mut connector = Connector::new().proxy(proxy);
+        let stream = connector
+            .call("http://api.ipify.org/".parse().unwrap())
+            .await
+            .unwrap();
+
+        assert!(stream.is_proxied());
+        assert!(stream.connected().is_proxied());
+    }
+
+    #[tokio::test]
+    async fn connector_proxy_auth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut headers = Vec::new();
+            for body_len in &[4, 0] {
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                let mut body = vec![0; *body_len];
+                socket.read_exact(&mut body).await.unwrap();
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .await
+                    .unwrap();
+                headers.push(String::from_utf8(header).unwrap());
+            }
+            headers
+        });
+
+        let proxy: Uri = format!("http://user:pass@{}", addr).parse().unwrap();
+        let connector = Connector::new().proxy(proxy);
+        let client = connector
+            .auth_layer()
+            .layer(hyper::Client::builder().build::<_, hyper::Body>(connector));
+        let request = hyper::Request::post("http://example.com/a")
+            .header("x-long", "a".repeat(2048))
+            .body(hyper::Body::from("data"))
+            .unwrap();
+        let response = client.clone().oneshot(request).await.unwrap();
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let request = hyper::Request::get("http://example.com/b")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = client.oneshot(request).await.unwrap();
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let headers = server.await.unwrap();
+        assert!(headers[0].starts_with("POST http://example.com/a HTTP/1.1\r\n"));
+        assert!(headers[1].starts_with("GET http://example.com/b HTTP/1.1\r\n"));
+        for header in headers {
+            assert!(header.contains("proxy-authorization: Basic dXNlcjpwYXNz\r\n"));
+        }
+    }
+}
@@ -0,0 +1,604 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use uri::Uri;
+
+use crate::typed::days_from_civil;
+
+/// Public Suffix List from <https://publicsuffix.org/list/>, used when no
+/// list is given to `Jar::with_public_suffixes`. It is a snapshot taken when
+/// the crate was released, load a current copy for suffixes added since.
+/// Rules are matched against hosts as written, so internationalized rules only
+/// apply to hosts in their Unicode form, not to their punycode `xn--` form.
+/// Single-label domains are always public.
+const PUBLIC_SUFFIXES: &str = include_str!("public_suffix_list.dat");
+
+/// Storage for cookies shared between clients. `Client` stores every
+/// `Set-Cookie` of a response and asks for the `Cookie` header of a request.
+pub trait CookieStore: fmt::Debug + Send + Sync {
+    fn set_cookies(&self, uri: &Uri, set_cookies: &[String]);
+
+    fn cookies(&self, uri: &Uri) -> Option<String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<SameSite> {
+        match value.to_ascii_lowercase().as_str() {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => None,
+        }
+    }
+}
+
+/// Cookie as stored by `Jar`, after the rules of RFC 6265 section 5.3.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    pub(crate) domain: String,
+    pub(crate) host_only: bool,
+    pub(crate) path: String,
+    pub(crate) expires: Option<SystemTime>,
+    pub(crate) secure: bool,
+    pub(crate) http_only: bool,
+    pub(crate) same_site: Option<SameSite>,
+    pub(crate) creation: u64,
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` value received from `uri`. Returns `None` for a
+    /// malformed value or one the host is not allowed to set, the public
+    /// suffix check is left to the jar.
+    pub fn parse(set_cookie: &str, uri: &Uri) -> Option<Cookie> {
+        let host = uri.host_str().to_ascii_lowercase();
+        let mut attributes = set_cookie.split(';');
+        let pair = attributes.next()?;
+        let idx = pair.find('=')?;
+        let name = pair[..idx].trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: pair[idx + 1..].trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            creation: 0,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let mut attribute = attribute.splitn(2, '=');
+            let key = attribute.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = attribute.next().unwrap_or("").trim();
+            match key.as_str() {
+                "expires" => {
+                    if let Some(expires) = parse_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                }
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_match(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => {
+                    if let Some(same_site) = SameSite::parse(value) {
+                        cookie.same_site = Some(same_site);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if let Some(seconds) = max_age {
+            cookie.expires = Some(if seconds <= 0 {
+                UNIX_EPOCH
+            } else {
+                SystemTime::now()
+                    .checked_add(Duration::from_secs(seconds as u64))
+                    .map_or_else(far_future, |expires| expires.min(far_future()))
+            });
+        }
+        if cookie.secure && !uri.is_ssl() {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Sent only to the exact host that set it, as it had no `Domain`.
+    pub fn is_host_only(&self) -> bool {
+        self.host_only
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// `None` for a session cookie.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// Stored as received, a client without browsing contexts has nothing to
+    /// enforce it against.
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => expires <= SystemTime::now(),
+            None => false,
+        }
+    }
+
+    /// Whether the cookie goes with a request to `uri`.
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let host = uri.host_str().to_ascii_lowercase();
+        let domain = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain && path_match(&request_path(uri), &self.path) && (!self.secure || uri.is_ssl())
+    }
+
+    fn same_key(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// Public suffix rules in the format of <https://publicsuffix.org/list/>.
+#[derive(Debug, Clone, Default)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    pub fn parse(list: &str) -> PublicSuffixList {
+        let mut suffixes = PublicSuffixList::default();
+        for line in list.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(rule) if !rule.starts_with("//") => rule.to_ascii_lowercase(),
+                _ => continue,
+            };
+            if let Some(rule) = rule.strip_prefix('!') {
+                suffixes.exceptions.insert(rule.to_string());
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                suffixes.wildcards.insert(rule.to_string());
+            } else {
+                suffixes.rules.insert(rule);
+            }
+        }
+        suffixes
+    }
+
+    pub fn is_public_suffix(&self, domain: &str) -> bool {
+        if self.exceptions.contains(domain) {
+            return false;
+        }
+        match domain.find('.') {
+            Some(idx) => self.rules.contains(domain) || self.wildcards.contains(&domain[idx + 1..]),
+            None => true,
+        }
+    }
+}
+
+/// In-memory `CookieStore`.
+#[derive(Debug)]
+pub struct Jar {
+    inner: Mutex<Inner>,
+    pub(crate) suffixes: PublicSuffixList,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cookies: Vec<Cookie>,
+    creation: u64,
+}
+
+impl Jar {
+    /// Jar rejecting cookies for the public suffixes of the bundled list.
+    pub fn new() -> Jar {
+        Jar::with_public_suffixes(PublicSuffixList::parse(PUBLIC_SUFFIXES))
+    }
+
+    pub fn with_public_suffixes(suffixes: PublicSuffixList) -> Jar {
+        Jar {
+            inner: Mutex::new(Inner::default()),
+            suffixes,
+        }
+    }
+
+    /// Stores a `Set-Cookie` value received from `uri`. A cookie for a public
+    /// suffix is only kept as a host-only cookie of that exact host.
+    pub fn add_cookie_str(&self, set_cookie: &str, uri: &Uri) {
+        let mut cookie = match Cookie::parse(set_cookie, uri) {
+            Some(cookie) => cookie,
+            None => return,
+        };
+        if !cookie.host_only && self.suffixes.is_public_suffix(&cookie.domain) {
+            if cookie.domain == uri.host_str().to_ascii_lowercase() {
+                cookie.host_only = true;
+            } else {
+                return;
+            }
+        }
+        self.insert(cookie);
+    }
+
+    /// Adds `cookie`, replacing one with the same name, domain and path. An
+    /// expired cookie only removes the one it replaces.
+    pub fn insert(&self, mut cookie: Cookie) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+        let old = inner
+            .cookies
+            .iter()
+            .position(|stored| stored.same_key(&cookie))
+            .map(|idx| inner.cookies.remove(idx));
+        if cookie.is_expired() {
+            return;
+        }
+        cookie.creation = match old {
+            Some(old) => old.creation,
+            None => {
+                inner.creation += 1;
+                inner.creation
+            }
+        };
+        inner.cookies.push(cookie);
+    }
+
+    /// Cookies for a request to `uri`, longest path first.
+    pub fn matching(&self, uri: &Uri) -> Vec<Cookie> {
+        let mut cookies: Vec<Cookie> = self
+            .all()
+            .into_iter()
+            .filter(|cookie| cookie.matches(uri))
+            .collect();
+        cookies.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.creation.cmp(&b.creation))
+        });
+        cookies
+    }
+
+    /// Every cookie that has not expired.
+    pub fn all(&self) -> Vec<Cookie> {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.cookies.retain(|cookie| !cookie.is_expired());
+                inner.cookies.clone()
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.cookies.clear();
+        }
+    }
+}
+
+impl Default for Jar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CookieStore for Jar {
+    fn set_cookies(&self, uri: &Uri, set_cookies: &[String]) {
+        for set_cookie in set_cookies {
+            self.add_cookie_str(set_cookie, uri);
+        }
+    }
+
+    fn cookies(&self, uri: &Uri) -> Option<String> {
+        let cookies = self.matching(uri);
+        if cookies.is_empty() {
+            None
+        } else {
+            Some(
+                cookies
+                    .iter()
+                    .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                    .collect::<Vec<String>>()
+                    .join("; "),
+            )
+        }
+    }
+}
+
+/// `Arc<dyn CookieStore>` compared by identity, so `ClientBuilder` keeps its
+/// `Debug` and `PartialEq`.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedStore(pub(crate) Arc<dyn CookieStore>);
+
+impl PartialEq for SharedStore {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Latest expiry time kept, the end of year 9999; later ones are clamped to
+/// it.
+pub(crate) fn far_future() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(253_402_300_799)
+}
+
+fn request_path(uri: &Uri) -> String {
+    let path = uri.abs_path();
+    match path.find('?') {
+        Some(idx) => path[..idx].to_string(),
+        None => path.to_string(),
+    }
+}
+
+fn default_path(uri: &Uri) -> String {
+    let path = request_path(uri);
+    match path.rfind('/') {
+        Some(idx) if idx > 0 && path.starts_with('/') => path[..idx].to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// Date of an `Expires` attribute, following RFC 6265 section 5.1.1.
+pub(crate) fn parse_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let tokens = value
+        .split(|c: char| {
+            c == '\t'
+                || (' '..='/').contains(&c)
+                || (';'..='@').contains(&c)
+                || ('['..='`').contains(&c)
+                || ('{'..='~').contains(&c)
+        })
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        let digits = token.chars().take_while(|c| c.is_ascii_digit()).count();
+        if time.is_none() {
+            let parts: Vec<&str> = token.splitn(3, ':').collect();
+            if parts.len() == 3 {
+                let numbers: Vec<u64> = parts
+                    .iter()
+                    .filter_map(|part| {
+                        let part = &part[..part.chars().take_while(|c| c.is_ascii_digit()).count()];
+                        if (1..=2).contains(&part.len()) {
+                            part.parse().ok()
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if numbers.len() == 3 {
+                    time = Some((numbers[0], numbers[1], numbers[2]));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() && (1..=2).contains(&digits) {
+            day = token[..digits].parse::<u64>().ok();
+            continue;
+        }
+        if month.is_none() && token.len() >= 3 {
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(idx) = MONTHS.iter().position(|m| *m == prefix) {
+                month = Some(idx as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && (2..=4).contains(&digits) {
+            year = token[..digits].parse::<u64>().ok();
+        }
+    }
+    let year = match year? {
+        year @ 70..=99 => year + 1900,
+        year @ 0..=69 => year + 2000,
+        year => year,
+    };
+    let (hour, minute, second) = time?;
+    let (day, month) = (day?, month?);
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    if seconds < 0 {
+        Some(UNIX_EPOCH)
+    } else {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(value: &str) -> Uri {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn cookie_parse_date() {
+        let expected = UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+        assert_eq!(parse_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(expected));
+        assert_eq!(
+            parse_date("Wednesday, 21-Oct-15 07:28:00 GMT"),
+            Some(expected)
+        );
+        assert_eq!(parse_date("Wed Oct 21 07:28:00 2015"), Some(expected));
+        assert_eq!(parse_date("Wed, 32 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn cookie_parse() {
+        let cookie = Cookie::parse(
+            "sid=abc; Domain=.Example.com; Path=/app; Secure; HttpOnly; SameSite=Lax",
+            &uri("https://www.example.com/login"),
+        )
+        .unwrap();
+        assert_eq!(cookie.name(), "sid");
+        assert_eq!(cookie.value(), "abc");
+        assert_eq!(cookie.domain(), "example.com");
+        assert!(!cookie.is_host_only());
+        assert_eq!(cookie.path(), "/app");
+        assert!(cookie.is_secure() && cookie.is_http_only());
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+
+        let cookie = Cookie::parse("a=1", &uri("http://example.com/a/b/c")).unwrap();
+        assert!(cookie.is_host_only());
+        assert_eq!(cookie.path(), "/a/b");
+
+        assert!(Cookie::parse("a=1; Domain=other.com", &uri("http://example.com/")).is_none());
+        assert!(Cookie::parse("a=1; Secure", &uri("http://example.com/")).is_none());
+        assert!(Cookie::parse("=1", &uri("http://example.com/")).is_none());
+        assert!(Cookie::parse("a=1; Max-Age=0", &uri("http://example.com/"))
+            .unwrap()
+            .is_expired());
+
+        let cookie = Cookie::parse(
+            &format!("a=1; Max-Age={}", i64::MAX),
+            &uri("http://example.com/"),
+        )
+        .unwrap();
+        assert_eq!(cookie.expires(), Some(far_future()));
+    }
+
+    #[test]
+    fn jar_matching() {
+        let jar = Jar::new();
+        let origin = uri("https://www.example.com/");
+        jar.set_cookies(
+            &origin,
+            &[
+                "a=1; Path=/".to_string(),
+                "b=2; Path=/docs; Domain=example.com".to_string(),
+                "c=3; Secure".to_string(),
+            ],
+        );
+
+        assert_eq!(
+            jar.cookies(&uri("https://www.example.com/docs/x")),
+            Some("b=2; a=1; c=3".to_string())
+        );
+        assert_eq!(
+            jar.cookies(&uri("http://example.com/docs")),
+            Some("b=2".to_string())
+        );
+        assert_eq!(jar.cookies(&uri("http://other.com/")), None);
+
+        jar.add_cookie_str("a=; Max-Age=0", &origin);
+        assert_eq!(
+            jar.cookies(&uri("https://www.example.com/")),
+            Some("c=3".to_string())
+        );
+    }
+
+    #[test]
+    fn jar_public_suffix() {
+        let jar = Jar::new();
+        jar.add_cookie_str("a=1; Domain=co.uk", &uri("http://shop.co.uk/"));
+        jar.add_cookie_str("b=2; Domain=com", &uri("http://example.com/"));
+        assert!(jar.cookies(&uri("http://shop.co.uk/")).is_none());
+        assert!(jar.cookies(&uri("http://example.com/")).is_none());
+
+        jar.add_cookie_str("c=3; Domain=github.io", &uri("http://github.io/"));
+        assert!(jar.all().iter().all(|cookie| cookie.is_host_only()));
+
+        let jar = Jar::new();
+        for suffix in &["com.pl", "co.in", "com.mx", "net.ru", "github.io"] {
+            assert!(jar.suffixes.is_public_suffix(suffix));
+        }
+        assert!(!jar.suffixes.is_public_suffix("example.com.pl"));
+
+        let suffixes = PublicSuffixList::parse("// comment\n*.ck\n!www.ck\n");
+        assert!(suffixes.is_public_suffix("any.ck"));
+        assert!(!suffixes.is_public_suffix("www.ck"));
+        assert!(!suffixes.is_public_suffix("example.com"));
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "serde")]
+use serde_json::{json, Value};
+
+#[cfg(feature = "serde")]
+use crate::cookie::SameSite;
+use crate::cookie::{far_future, Cookie, Jar};
+use crate::error::{Error, Result};
+#[cfg(feature = "serde")]
+use crate::json::json_error;
+
+const NETSCAPE_HEADER: &str =
+    "# Netscape HTTP Cookie File\n# Written by netc, edit at your own risk.\n\n";
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// The `cookies.txt` format of curl, wget and old browsers. `SameSite` is
+    /// not part of it and is lost.
+    Netscape,
+    /// JSON array keeping every attribute.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// Which cookies are read or written, by default every cookie that has not
+/// expired. A `Jar` drops expired cookies as they are added, so keeping them
+/// only matters for the functions working on cookie lists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    expired: bool,
+    session: bool,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter {
+            expired: false,
+            session: true,
+        }
+    }
+
+    pub fn expired(mut self, keep: bool) -> Filter {
+        self.expired = keep;
+        self
+    }
+
+    /// Whether cookies without an expiry date, which a browser drops when it
+    /// closes, are kept.
+    pub fn session(mut self, keep: bool) -> Filter {
+        self.session = keep;
+        self
+    }
+
+    pub fn keeps(&self, cookie: &Cookie) -> bool {
+        (self.session || cookie.expires.is_some()) && (self.expired || !cookie.is_expired())
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jar {
+    /// Adds the cookies of the file at `path`, returns how many were added.
+    /// Domain cookies for a public suffix are skipped, as they are when
+    /// received.
+    pub async fn load<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: Format,
+        filter: Filter,
+    ) -> Result<usize> {
+        let text = tokio::fs::read_to_string(path).await?;
+        let cookies = match format {
+            Format::Netscape => read_netscape(&text, filter)?,
+            #[cfg(feature = "serde")]
+            Format::Json => read_json(&text, filter)?,
+        };
+        let mut count = 0;
+        for cookie in cookies {
+            if !cookie.host_only && self.suffixes.is_public_suffix(&cookie.domain) {
+                continue;
+            }
+            self.insert(cookie);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes the cookies to the file at `path`, replacing it.
+    pub async fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: Format,
+        filter: Filter,
+    ) -> Result<()> {
+        let mut cookies = self.all();
+        cookies.sort_by_key(|cookie| cookie.creation);
+        let text = match format {
+            Format::Netscape => write_netscape(&cookies, filter),
+            #[cfg(feature = "serde")]
+            Format::Json => write_json(&cookies, filter),
+        };
+        tokio::fs::write(path, text).await?;
+        Ok(())
+    }
+}
+
+/// Parses a Netscape `cookies.txt`. A malformed line fails with
+/// `Error::CookieFile` and its line number.
+pub fn read_netscape(text: &str, filter: Filter) -> Result<Vec<Cookie>> {
+    let mut cookies = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(7, '\t').collect();
+        if fields.len() != 7 {
+            return Err(Error::CookieFile(idx + 1));
+        }
+        let seconds = fields[4]
+            .parse::<u64>()
+            .map_err(|_| Error::CookieFile(idx + 1))?;
+        let cookie = Cookie {
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+            domain: fields[0].trim_start_matches('.').to_ascii_lowercase(),
+            host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+            path: fields[2].to_string(),
+            expires: from_unix(seconds),
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            http_only,
+            same_site: None,
+            creation: 0,
+        };
+        if cookie.name.is_empty() || cookie.domain.is_empty() {
+            return Err(Error::CookieFile(idx + 1));
+        }
+        if filter.keeps(&cookie) {
+            cookies.push(cookie);
+        }
+    }
+    Ok(cookies)
+}
+
+/// Writes `cookies` as a Netscape `cookies.txt`, session cookies get an
+/// expiry of 0 as curl does.
+pub fn write_netscape(cookies: &[Cookie], filter: Filter) -> String {
+    let mut text = NETSCAPE_HEADER.to_string();
+    for cookie in cookies.iter().filter(|cookie| filter.keeps(cookie)) {
+        text.push_str(&format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if cookie.http_only {
+                HTTP_ONLY_PREFIX
+            } else {
+                ""
+            },
+            if cookie.host_only { "" } else { "." },
+            cookie.domain,
+            if cookie.host_only { "FALSE" } else { "TRUE" },
+            cookie.path,
+            if cookie.secure { "TRUE" } else { "FALSE" },
+            to_unix(cookie.expires),
+            cookie.name,
+            cookie.value
+        ));
+    }
+    text
+}
+
+/// Parses cookies written by `write_json`. An entry missing a field fails
+/// with `Error::CookieFile` and its position, counted from 1.
+#[cfg(feature = "serde")]
+pub fn read_json(text: &str, filter: Filter) -> Result<Vec<Cookie>> {
+    let values: Vec<Value> =
+        serde_json::from_str(text).map_err(|err| json_error(err, text.as_bytes()))?;
+    let mut cookies = Vec::new();
+    for (idx, value) in values.iter().enumerate() {
+        let cookie = json_cookie(value).ok_or(Error::CookieFile(idx + 1))?;
+        if filter.keeps(&cookie) {
+            cookies.push(cookie);
+        }
+    }
+    Ok(cookies)
+}
+
+/// Writes `cookies` as a JSON array of objects, `expires` is in seconds since
+/// the Unix epoch and `null` for session cookies.
+#[cfg(feature = "serde")]
+pub fn write_json(cookies: &[Cookie], filter: Filter) -> String {
+    let values: Vec<Value> = cookies
+        .iter()
+        .filter(|cookie| filter.keeps(cookie))
+        .map(|cookie| {
+            json!({
+                "name": cookie.name,
+                "value": cookie.value,
+                "domain": cookie.domain,
+                "host_only": cookie.host_only,
+                "path": cookie.path,
+                "expires": cookie.expires.map(|expires| to_unix(Some(expires))),
+                "secure": cookie.secure,
+                "http_only": cookie.http_only,
+                "same_site": cookie.same_site.map(|same_site| same_site.as_str()),
+            })
+        })
+        .collect();
+    format!("{:#}\n", Value::Array(values))
+}
+
+#[cfg(feature = "serde")]
+fn json_cookie(value: &Value) -> Option<Cookie> {
+    let text = |key: &str| value.get(key)?.as_str().map(|text| text.to_string());
+    let flag = |key: &str| value.get(key)?.as_bool();
+    let expires = match value.get("expires") {
+        Some(Value::Null) | None => None,
+        Some(expires) => Some(from_unix(expires.as_u64()?).unwrap_or(UNIX_EPOCH)),
+    };
+    let same_site = match value.get("same_site") {
+        Some(Value::Null) | None => None,
+        Some(same_site) => Some(SameSite::parse(same_site.as_str()?)?),
+    };
+    Some(Cookie {
+        name: text("name")?,
+        value: text("value")?,
+        domain: text("domain")?,
+        host_only: flag("host_only")?,
+        path: text("path")?,
+        expires,
+        secure: flag("secure")?,
+        http_only: flag("http_only")?,
+        same_site,
+        creation: 0,
+    })
+}
+
+/// `None` for 0, the session marker of `cookies.txt`. Times past the end of
+/// year 9999 are clamped to it.
+fn from_unix(seconds: u64) -> Option<SystemTime> {
+    if seconds == 0 {
+        None
+    } else {
+        let expires = UNIX_EPOCH.checked_add(Duration::from_secs(seconds));
+        Some(expires.map_or_else(far_future, |expires| expires.min(far_future())))
+    }
+}
+
+fn to_unix(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs().max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uri::Uri;
+
+    const COOKIES_TXT: &str = "# Netscape HTTP Cookie File\n\
+        .example.com\tTRUE\t/\tFALSE\t4102444800\tsid\tabc\n\
+        #HttpOnly_www.example.com\tFALSE\t/app\tTRUE\t4102444800\ttoken\tx=y\n\
+        example.com\tFALSE\t/\tFALSE\t0\tsession\t\n\
+        example.com\tFALSE\t/\tFALSE\t1\told\t1\n";
+
+    #[test]
+    fn netscape_read() {
+        let cookies = read_netscape(COOKIES_TXT, Filter::new()).unwrap();
+        assert_eq!(cookies.len(), 3);
+        assert!(!cookies[0].is_host_only());
+        assert_eq!(cookies[0].domain(), "example.com");
+        assert!(cookies[1].is_http_only() && cookies[1].is_secure());
+        assert_eq!(cookies[1].value(), "x=y");
+        assert_eq!(cookies[2].expires(), None);
+
+        let cookies =
+            read_netscape(COOKIES_TXT, Filter::new().session(false).expired(true)).unwrap();
+        let names: Vec<&str> = cookies.iter().map(|cookie| cookie.name()).collect();
+        assert_eq!(names, vec!["sid", "token", "old"]);
+
+        assert_eq!(
+            read_netscape("# comment\nexample.com\tFALSE\t/\n", Filter::new()),
+            Err(Error::CookieFile(2))
+        );
+
+        let cookies = read_netscape(
+            &format!("example.com\tFALSE\t/\tFALSE\t{}\tfar\t1\n", u64::MAX),
+            Filter::new(),
+        )
+        .unwrap();
+        assert_eq!(cookies[0].expires(), Some(far_future()));
+    }
+
+    #[test]
+    fn netscape_round_trip() {
+        let cookies = read_netscape(COOKIES_TXT, Filter::new().expired(true)).unwrap();
+        let text = write_netscape(&cookies, Filter::new().expired(true));
+        assert!(text.starts_with("# Netscape HTTP Cookie File\n"));
+        assert!(
+            text.contains("#HttpOnly_www.example.com\tFALSE\t/app\tTRUE\t4102444800\ttoken\tx=y\n")
+        );
+        assert_eq!(
+            read_netscape(&text, Filter::new().expired(true)).unwrap(),
+            cookies
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let uri: Uri = "https://www.example.com/".parse().unwrap();
+        let cookies = vec![
+            Cookie::parse("a=1; Max-Age=3600; SameSite=Strict; HttpOnly", &uri).unwrap(),
+            Cookie::parse("b=2; Domain=example.com; Secure", &uri).unwrap(),
+        ];
+        let text = write_json(&cookies, Filter::new());
+        let read = read_json(&text, Filter::new()).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].same_site(), Some(SameSite::Strict));
+        assert!(read[0].expires().is_some() && read[0].is_http_only());
+        assert_eq!(read[1], cookies[1]);
+
+        assert_eq!(
+            read_json(&text, Filter::new().session(false))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            read_json("[{\"name\": \"a\"}]", Filter::new()),
+            Err(Error::CookieFile(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn jar_save_load() {
+        let uri: Uri = "http://example.com/".parse().unwrap();
+        let jar = Jar::new();
+        jar.add_cookie_str("a=1; Max-Age=3600", &uri);
+        jar.add_cookie_str("b=2", &uri);
+        let path = std::env::temp_dir().join(format!("netc-cookies-{}.txt", std::process::id()));
+
+        jar.save(&path, Format::Netscape, Filter::new().session(false))
+            .await
+            .unwrap();
+        let loaded = Jar::new();
+        let count = loaded
+            .load(&path, Format::Netscape, Filter::new())
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(
+            crate::cookie::CookieStore::cookies(&loaded, &uri),
+            Some("a=1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn jar_load_public_suffix() {
+        let path = std::env::temp_dir().join(format!("netc-suffix-{}.txt", std::process::id()));
+        tokio::fs::write(
+            &path,
+            ".com\tTRUE\t/\tFALSE\t4102444800\twide\t1\n\
+             .example.com\tTRUE\t/\tFALSE\t4102444800\tsid\tabc\n",
+        )
+        .await
+        .unwrap();
+        let jar = Jar::new();
+        let count = jar
+            .load(&path, Format::Netscape, Filter::new())
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(count, 1);
+        let uri: Uri = "http://example.com/".parse().unwrap();
+        assert_eq!(
+            crate::cookie::CookieStore::cookies(&jar, &uri),
+            Some("sid=abc".to_string())
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use std::cmp;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::error::{Error, Result};
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+
+const MAX_HEADER_SIZE: usize = 1024;
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event<H = Response> {
+    Head(H),
+    Body(Bytes),
+    End,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+    Head,
+    Length(usize),
+    ChunkSize,
+    ChunkData(usize),
+    ChunkDataEnd,
+    Trailer,
+    Eof,
+    Done,
+}
+
+/// Push-based HTTP/1.x response decoder. Bytes are fed in with `feed`, the end
+/// of the transport is signalled with `finish` and events are pulled out with
+/// `decode` until it returns `None`.
+#[derive(Debug, Default)]
+pub struct ResponseDecoder {
+    framing: Framing,
+    method: Method,
+}
+
+impl ResponseDecoder {
+    pub fn new() -> ResponseDecoder {
+        ResponseDecoder {
+            framing: Framing::new(),
+            method: Method::GET,
+        }
+    }
+
+    /// Responses to HEAD and successful responses to CONNECT never carry a
+    /// body, whatever their headers say. Interim 1xx responses are skipped
+    /// except `101 Switching Protocols`, which ends HTTP on the connection
+    /// and is returned as the final response.
+    pub fn with_method(method: &Method) -> ResponseDecoder {
+        let mut decoder = ResponseDecoder::new();
+        decoder.method = method.clone();
+        decoder
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.framing.buf.extend_from_slice(data);
+    }
+
+    pub fn finish(&mut self) {
+        self.framing.eof = true;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.framing.state == State::Done
+    }
+
+    /// Bytes received after the end of the message.
+    pub fn remaining(&self) -> &[u8] {
+        &self.framing.buf
+    }
+
+    pub fn decode(&mut self) -> Result<Option<Event>> {
+        let method = &self.method;
+        self.framing.decode(Error::EmptyResponse, |header| {
+            let response = Response::from_header(header)?;
+            let code = response.status_code();
+            let switching = code.is(|c| c == 101);
+            if code.is_info() && !switching {
+                return Ok(None);
+            }
+            let no_body = switching
+                || *method == Method::HEAD
+                || (*method == Method::CONNECT && code.is_success())
+                || code.is(|c| c == 204 || c == 304);
+            let state = if no_body {
+                State::Length(0)
+            } else {
+                match response.headers().get("Transfer-Encoding") {
+                    Some(encoding) if is_chunked(&encoding) => State::ChunkSize,
+                    Some(_) => State::Eof,
+                    None => match response.headers().get("Content-Length") {
+                        Some(_) => State::Length(response.content_len()?),
+                        None => State::Eof,
+                    },
+                }
+            };
+            Ok(Some((response, state)))
+        })
+    }
+}
+
+/// Push-based HTTP/1.x request decoder, the server-side counterpart of
+/// `ResponseDecoder`. A request without `Content-Length` or chunked
+/// `Transfer-Encoding` has no body. An invalid `Content-Length`, or repeated
+/// ones that disagree, fail with `Error::HeadersErr` rather than letting the
+/// body be read as the next request.
+#[derive(Debug, Default)]
+pub struct RequestDecoder {
+    framing: Framing,
+}
+
+impl RequestDecoder {
+    pub fn new() -> RequestDecoder {
+        RequestDecoder {
+            framing: Framing::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.framing.buf.extend_from_slice(data);
+    }
+
+    pub fn finish(&mut self) {
+        self.framing.eof = true;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.framing.state == State::Done
+    }
+
+    /// Bytes received after the end of the message.
+    pub fn remaining(&self) -> &[u8] {
+        &self.framing.buf
+    }
+
+    pub fn decode(&mut self) -> Result<Option<Event<Request>>> {
+        self.framing.decode(Error::EmptyRequest, |header| {
+            let request = Request::from_header(header)?;
+            let headers = request.get_headers();
+            let state = match headers.get("Transfer-Encoding") {
+                Some(encoding) if is_chunked(&encoding) => State::ChunkSize,
+                Some(_) => return Err(Error::HeadersErr),
+                None => match headers.get("Content-Length") {
+                    Some(value) => State::Length(parse_content_length(&value)?),
+                    None => State::Length(0),
+                },
+            };
+            Ok(Some((request, state)))
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Framing {
+    state: State,
+    buf: BytesMut,
+    eof: bool,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framing {
+    fn new() -> Framing {
+        Framing {
+            state: State::Head,
+            buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    /// `parse_head` turns the raw header into the message head and the body
+    /// framing, or returns `None` to skip an interim message.
+    fn decode<H, F>(&mut self, empty: Error, mut parse_head: F) -> Result<Option<Event<H>>>
+    where
+        F: FnMut(&[u8]) -> Result<Option<(H, State)>>,
+    {
+        loop {
+            match self.state {
+                State::Head => {
+                    let pos = match find_crlf2(&self.buf) {
+                        Some(pos) => pos,
+                        None if self.buf.len() > MAX_HEADER_SIZE => return Err(Error::HeaderToBig),
+                        None if self.eof && self.buf.is_empty() => return Err(empty),
+                        None if self.eof => return Err(Error::HeaderIncomplete),
+                        None => return Ok(None),
+                    };
+                    if pos > MAX_HEADER_SIZE {
+                        return Err(Error::HeaderToBig);
+                    }
+                    let header = self.buf.split_to(pos);
+                    if let Some((head, state)) = parse_head(&header)? {
+                        self.state = state;
+                        return Ok(Some(Event::Head(head)));
+                    }
+                }
+                State::Length(0) => {
+                    self.state = State::Done;
+                    return Ok(Some(Event::End));
+                }
+                State::Length(remaining) => match self.take(remaining) {
+                    Some(chunk) => {
+                        self.state = State::Length(remaining - chunk.len());
+                        return Ok(Some(Event::Body(chunk)));
+                    }
+                    None => return self.need_more(),
+                },
+                State::ChunkSize => match self.take_line()? {
+                    Some(line) => {
+                        let size = parse_chunk_size(&line)?;
+                        self.state = if size == 0 {
+                            State::Trailer
+                        } else {
+                            State::ChunkData(size)
+                        };
+                    }
+                    None => return self.need_more(),
+                },
+                State::ChunkData(remaining) => match self.take(remaining) {
+                    Some(chunk) => {
+                        self.state = match remaining - chunk.len() {
+                            0 => State::ChunkDataEnd,
+                            remaining => State::ChunkData(remaining),
+                        };
+                        return Ok(Some(Event::Body(chunk)));
+                    }
+                    None => return self.need_more(),
+                },
+                State::ChunkDataEnd => match self.take_line()? {
+                    Some(line) if line.is_empty() => self.state = State::ChunkSize,
+                    Some(_) => return Err(Error::InvalidChunk),
+                    None => return self.need_more(),
+                },
+                State::Trailer => match self.take_line()? {
+                    Some(line) if line.is_empty() => {
+                        self.state = State::Done;
+                        return Ok(Some(Event::End));
+                    }
+                    Some(_) => continue,
+                    None => return self.need_more(),
+                },
+                State::Eof if !self.buf.is_empty() => {
+                    return Ok(Some(Event::Body(self.buf.split().freeze())))
+                }
+                State::Eof if self.eof => {
+                    self.state = State::Done;
+                    return Ok(Some(Event::End));
+                }
+                State::Eof => return Ok(None),
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    fn need_more<H>(&self) -> Result<Option<Event<H>>> {
+        if self.eof {
+            Err(Error::BodyIncomplete)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn take(&mut self, max: usize) -> Option<Bytes> {
+        if self.buf.is_empty() {
+            None
+        } else {
+            let len = cmp::min(max, self.buf.len());
+            Some(self.buf.split_to(len).freeze())
+        }
+    }
+
+    fn take_line(&mut self) -> Result<Option<Bytes>> {
+        match self.buf.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => {
+                let line = self.buf.split_to(pos).freeze();
+                let _ = self.buf.split_to(2);
+                Ok(Some(line))
+            }
+            None if self.buf.len() > MAX_CHUNK_LINE => Err(Error::InvalidChunk),
+            None => Ok(None),
+        }
+    }
+}
+
+pub(crate) fn is_chunked(transfer_encoding: &str) -> bool {
+    let coding = transfer_encoding.rsplit(',').next().unwrap_or("");
+    coding.trim().eq_ignore_ascii_case("chunked")
+}
+
+fn find_crlf2(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+/// Length from a `Content-Length` field, repeated values joined with ", "
+/// must all be the same.
+fn parse_content_length(value: &str) -> Result<usize> {
+    let mut lengths = value.split(',').map(|length| {
+        let length = length.trim();
+        if length.is_empty() || !length.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(Error::HeadersErr);
+        }
+        length.parse::<usize>().or(Err(Error::HeadersErr))
+    });
+    let first = lengths.next().ok_or(Error::HeadersErr)??;
+    for length in lengths {
+        if length? != first {
+            return Err(Error::HeadersErr);
+        }
+    }
+    Ok(first)
+}
+
+fn parse_chunk_size(line: &[u8]) -> Result<usize> {
+    let line = std::str::from_utf8(line).or(Err(Error::InvalidChunk))?;
+    let size = line.split(';').next().unwrap_or("").trim();
+    usize::from_str_radix(size, 16).or(Err(Error::InvalidChunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusCode;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                              Content-Type: text/html\r\n\
+                              Content-Length: 18\r\n\r\n\
+                              <html>hello</html>";
+    const CHUNKED: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                             Transfer-Encoding: chunked\r\n\r\n\
+                             6\r\n<html>\r\n\
+                             c;ext=1\r\nhello</html>\r\n\
+                             0\r\nExpires: never\r\n\r\n";
+    const BODY: &[u8] = b"<html>hello</html>";
+
+    fn collect(decoder: &mut ResponseDecoder) -> (Option<Response>, Vec<u8>, bool) {
+        let mut head = None;
+        let mut body = Vec::new();
+        let mut end = false;
+        while let Some(event) = decoder.decode().unwrap() {
+            match event {
+                Event::Head(response) => head = Some(response),
+                Event::Body(chunk) => body.extend_from_slice(&chunk),
+                Event::End => end = true,
+            }
+        }
+        (head, body, end)
+    }
+
+    #[test]
+    fn decoder_content_length() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(RESPONSE);
+        let (head, body, end) = collect(&mut decoder);
+
+        assert_eq!(head.unwrap().status_code(), StatusCode::from(200));
+        assert_eq!(body, BODY);
+        assert!(end);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn decoder_byte_by_byte() {
+        let mut decoder = ResponseDecoder::new();
+        let mut body = Vec::new();
+        let mut heads = 0;
+        for byte in CHUNKED {
+            decoder.feed(&[*byte]);
+            let (head, chunk, _) = collect(&mut decoder);
+            heads += head.map_or(0, |_| 1);
+            body.extend_from_slice(&chunk);
+        }
+
+        assert_eq!(heads, 1);
+        assert_eq!(body, BODY);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn decoder_chunked() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(CHUNKED);
+        let (_, body, end) = collect(&mut decoder);
+
+        assert_eq!(body, BODY);
+        assert!(end);
+    }
+
+    #[test]
+    fn decoder_bad_chunk() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n");
+
+        assert!(matches!(decoder.decode(), Ok(Some(Event::Head(_)))));
+        assert_eq!(decoder.decode(), Err(Error::InvalidChunk));
+    }
+
+    #[test]
+    fn decoder_until_eof() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(b"HTTP/1.0 200 OK\r\nContent-Type: text/html\r\n\r\n<html>");
+        let (_, mut body, end) = collect(&mut decoder);
+        assert!(!end);
+        decoder.feed(b"hello</html>");
+        decoder.finish();
+        let (_, chunk, end) = collect(&mut decoder);
+        body.extend_from_slice(&chunk);
+
+        assert_eq!(body, BODY);
+        assert!(end);
+    }
+
+    #[test]
+    fn decoder_head_request() {
+        let mut decoder = ResponseDecoder::with_method(&Method::HEAD);
+        decoder.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n");
+        let (head, body, end) = collect(&mut decoder);
+
+        assert!(head.is_some());
+        assert!(body.is_empty());
+        assert!(end);
+    }
+
+    #[test]
+    fn decoder_connect_request() {
+        let mut decoder = ResponseDecoder::with_method(&Method::CONNECT);
+        decoder.feed(b"HTTP/1.1 200 Connection established\r\n\r\n");
+        let (head, _, end) = collect(&mut decoder);
+
+        assert!(head.is_some());
+        assert!(end);
+    }
+
+    #[test]
+    fn decoder_skip_continue() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(b"HTTP/1.1 100 Continue\r\n\r\n");
+        decoder.feed(RESPONSE);
+        let (head, body, _) = collect(&mut decoder);
+
+        assert_eq!(head.unwrap().status_code(), StatusCode::from(200));
+        assert_eq!(body, BODY);
+    }
+
+    #[test]
+    fn decoder_switching_protocols() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n\x81\x00");
+        let (head, body, end) = collect(&mut decoder);
+
+        assert_eq!(head.unwrap().status_code(), StatusCode::from(101));
+        assert!(body.is_empty());
+        assert!(end);
+        assert_eq!(decoder.remaining(), b"\x81\x00");
+    }
+
+    #[test]
+    fn decoder_incomplete() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.finish();
+        assert_eq!(decoder.decode(), Err(Error::EmptyResponse));
+
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(b"HTTP/1.1 200 OK\r\n");
+        decoder.finish();
+        assert_eq!(decoder.decode(), Err(Error::HeaderIncomplete));
+
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(&RESPONSE[..RESPONSE.len() - 1]);
+        let _ = collect(&mut decoder);
+        decoder.finish();
+        assert_eq!(decoder.decode(), Err(Error::BodyIncomplete));
+    }
+
+    #[test]
+    fn decoder_request_content_length() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nok");
+        assert!(matches!(decoder.decode(), Ok(Some(Event::Head(_)))));
+        assert!(matches!(decoder.decode(), Ok(Some(Event::Body(body))) if body == "ok"));
+
+        for head in [
+            &b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 5\r\n\r\n"[..],
+            b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: \r\n\r\n",
+        ]
+        .iter()
+        {
+            let mut decoder = RequestDecoder::new();
+            decoder.feed(head);
+            assert!(matches!(decoder.decode(), Err(Error::HeadersErr)));
+        }
+    }
+
+    #[test]
+    fn decoder_header_to_big() {
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(&[b'a'; MAX_HEADER_SIZE + 1]);
+
+        assert_eq!(decoder.decode(), Err(Error::HeaderToBig));
+    }
+}
@@ -0,0 +1,359 @@
+use std::{fmt, io};
+
+#[cfg(feature = "zstd")]
+use std::io::Write;
+#[cfg(any(feature = "deflate", feature = "brotli"))]
+use std::mem;
+
+use bytes::Bytes;
+
+#[cfg(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "brotli",
+    feature = "zstd"
+))]
+use crate::coding::write_take;
+use crate::coding::{self, Stages};
+use crate::error::{Error, Result};
+use crate::response::Response;
+
+/// Value for `Accept-Encoding` listing the codings built into the crate.
+pub fn accept_encoding() -> Option<String> {
+    let mut codings = Vec::new();
+    if cfg!(feature = "gzip") {
+        codings.push("gzip");
+    }
+    if cfg!(feature = "deflate") {
+        codings.push("deflate");
+    }
+    if cfg!(feature = "brotli") {
+        codings.push("br");
+    }
+    if cfg!(feature = "zstd") {
+        codings.push("zstd");
+    }
+    if codings.is_empty() {
+        None
+    } else {
+        Some(codings.join(", "))
+    }
+}
+
+/// Undoes every coding listed in a `Content-Encoding` value, last applied
+/// first.
+pub fn decode(content_encoding: &str, body: &[u8]) -> Result<Bytes> {
+    Decompressor::new(content_encoding)?.decode(body)
+}
+
+/// Replaces a coded response body with the decoded one, drops
+/// `Content-Encoding` and `Transfer-Encoding` and sets `Content-Length` to
+/// the decoded length. The original coding stays available through
+/// `Response::content_encoding`. A coding the crate cannot undo, or an empty
+/// body as HEAD and 304 responses have, leaves the response as it was sent.
+pub fn decompress(response: &mut Response) -> Result<()> {
+    if let Some(encoding) = response.headers().get("Content-Encoding") {
+        if response.body.is_empty() {
+            return Ok(());
+        }
+        let decompressor = match Decompressor::new(&encoding) {
+            Ok(decompressor) => decompressor,
+            Err(Error::UnsupportedEncoding(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        response.body = decompressor.decode(&response.body)?;
+        response.headers.remove("Content-Encoding");
+        response.headers.remove("Transfer-Encoding");
+        response
+            .headers
+            .insert("Content-Length", &response.body.len());
+        response.content_encoding = Some(encoding);
+    }
+    Ok(())
+}
+
+/// Streaming counterpart of `decode` for bodies read chunk by chunk, e.g.
+/// from `Event::Body`.
+pub struct Decompressor(Stages<Stage>);
+
+impl Decompressor {
+    pub fn new(content_encoding: &str) -> Result<Decompressor> {
+        Stages::new(content_encoding, content_encoding.rsplit(',')).map(Decompressor)
+    }
+
+    /// Decodes the next chunk of the body, returning whatever output is
+    /// already available.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Bytes> {
+        self.0.feed(chunk)
+    }
+
+    /// Decodes a whole body at once.
+    pub fn decode(mut self, body: &[u8]) -> Result<Bytes> {
+        let mut decoded = self.feed(body)?.to_vec();
+        decoded.extend_from_slice(&self.finish()?);
+        Ok(decoded.into())
+    }
+
+    /// Flushes the remaining output and checks that every coding ended
+    /// cleanly.
+    pub fn finish(self) -> Result<Bytes> {
+        self.0.finish()
+    }
+}
+
+impl fmt::Debug for Decompressor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt("Decompressor", f)
+    }
+}
+
+enum Stage {
+    Identity,
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::MultiGzDecoder<Vec<u8>>),
+    /// `deflate` is meant to be zlib wrapped, but raw deflate is common
+    /// enough that the first two bytes decide which one is used.
+    #[cfg(feature = "deflate")]
+    Deflate(Vec<u8>),
+    #[cfg(feature = "deflate")]
+    Zlib(flate2::write::ZlibDecoder<Vec<u8>>),
+    #[cfg(feature = "deflate")]
+    RawDeflate(flate2::write::DeflateDecoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli_decompressor::DecompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl coding::Stage for Stage {
+    fn new(coding: &str) -> Result<Stage> {
+        match coding {
+            "" | "identity" => Ok(Stage::Identity),
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => Ok(Stage::Gzip(flate2::write::MultiGzDecoder::new(Vec::new()))),
+            #[cfg(feature = "deflate")]
+            "deflate" => Ok(Stage::Deflate(Vec::new())),
+            #[cfg(feature = "brotli")]
+            "br" => Ok(Stage::Brotli(Box::new(
+                brotli_decompressor::DecompressorWriter::new(Vec::new(), 4096),
+            ))),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(Stage::Zstd(zstd::stream::write::Decoder::new(Vec::new())?)),
+            coding => Err(Error::UnsupportedEncoding(coding.to_string())),
+        }
+    }
+
+    fn write(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Stage::Identity => Ok(input.to_vec()),
+            #[cfg(feature = "gzip")]
+            Stage::Gzip(decoder) => write_take(decoder, input, true, |d| d.get_mut()),
+            #[cfg(feature = "deflate")]
+            Stage::Deflate(pending) => {
+                pending.extend_from_slice(input);
+                if pending.len() < 2 {
+                    return Ok(Vec::new());
+                }
+                let pending = mem::take(pending);
+                *self = Stage::deflate(&pending);
+                self.write(&pending)
+            }
+            #[cfg(feature = "deflate")]
+            Stage::Zlib(decoder) => write_take(decoder, input, true, |d| d.get_mut()),
+            #[cfg(feature = "deflate")]
+            Stage::RawDeflate(decoder) => write_take(decoder, input, true, |d| d.get_mut()),
+            #[cfg(feature = "brotli")]
+            Stage::Brotli(decoder) => write_take(decoder.as_mut(), input, true, |d| d.get_mut()),
+            #[cfg(feature = "zstd")]
+            Stage::Zstd(decoder) => write_take(decoder, input, true, |d| d.get_mut()),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Stage::Identity => Ok(Vec::new()),
+            #[cfg(feature = "gzip")]
+            Stage::Gzip(decoder) => decoder.finish(),
+            #[cfg(feature = "deflate")]
+            Stage::Deflate(pending) if pending.is_empty() => Ok(Vec::new()),
+            #[cfg(feature = "deflate")]
+            Stage::Deflate(pending) => {
+                let mut stage = Stage::deflate(&pending);
+                let mut decoded = stage.write(&pending)?;
+                decoded.extend(stage.finish()?);
+                Ok(decoded)
+            }
+            #[cfg(feature = "deflate")]
+            Stage::Zlib(decoder) => decoder.finish(),
+            #[cfg(feature = "deflate")]
+            Stage::RawDeflate(decoder) => decoder.finish(),
+            #[cfg(feature = "brotli")]
+            Stage::Brotli(mut decoder) => {
+                decoder.close()?;
+                Ok(mem::take(decoder.get_mut()))
+            }
+            #[cfg(feature = "zstd")]
+            Stage::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+impl Stage {
+    #[cfg(feature = "deflate")]
+    fn deflate(head: &[u8]) -> Stage {
+        let zlib = head.len() >= 2
+            && head[0] & 0x0f == 8
+            && (u16::from(head[0]) << 8 | u16::from(head[1])) % 31 == 0;
+        if zlib {
+            Stage::Zlib(flate2::write::ZlibDecoder::new(Vec::new()))
+        } else {
+            Stage::RawDeflate(flate2::write::DeflateDecoder::new(Vec::new()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_identity() {
+        assert_eq!(decode("identity", b"hello"), Ok(Bytes::from("hello")));
+        assert_eq!(
+            decode("unknown", b"hello"),
+            Err(Error::UnsupportedEncoding("unknown".to_string()))
+        );
+    }
+
+    #[test]
+    fn decompress_keeps_encoding() {
+        let mut response =
+            Response::from_header(b"HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\n\r\n")
+                .unwrap();
+        response.body = "hello".into();
+        decompress(&mut response).unwrap();
+
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.content_encoding(), Some("identity".to_string()));
+        assert_eq!(response.content_len(), Ok(5));
+    }
+
+    #[test]
+    fn decompress_framing() {
+        let mut response = Response::from_header(
+            b"HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\nTransfer-Encoding: chunked\r\n\r\n",
+        )
+        .unwrap();
+        response.body = "hello".into();
+        decompress(&mut response).unwrap();
+        assert_eq!(response.headers().get("Transfer-Encoding"), None);
+        assert_eq!(response.content_len(), Ok(5));
+
+        let mut response = Response::from_header(
+            b"HTTP/1.1 304 Not Modified\r\nContent-Encoding: identity\r\nContent-Length: 120\r\n\r\n",
+        )
+        .unwrap();
+        let sent = response.clone();
+        decompress(&mut response).unwrap();
+        assert_eq!(response, sent);
+    }
+
+    #[test]
+    fn decompress_unknown_encoding() {
+        let mut response =
+            Response::from_header(b"HTTP/1.1 200 OK\r\nContent-Encoding: unknown\r\n\r\n").unwrap();
+        response.body = "hello".into();
+        let sent = response.clone();
+        decompress(&mut response).unwrap();
+
+        assert_eq!(response, sent);
+        assert_eq!(response.content_encoding(), Some("unknown".to_string()));
+    }
+
+    #[cfg(all(feature = "gzip", feature = "deflate"))]
+    #[test]
+    fn decode_stacked() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"hello").unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&zlib.finish().unwrap()).unwrap();
+        let body = gzip.finish().unwrap();
+
+        assert_eq!(decode("deflate, gzip", &body), Ok(Bytes::from("hello")));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn decode_raw_deflate() {
+        use flate2::write::DeflateEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(b"hello").unwrap();
+        let body = deflate.finish().unwrap();
+
+        assert_eq!(decode("deflate", &body), Ok(Bytes::from("hello")));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decompressor_chunks() {
+        use crate::decoder::{Event, ResponseDecoder};
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let text = "hello world ".repeat(100);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let body = gzip.finish().unwrap();
+
+        let mut decoder = ResponseDecoder::new();
+        decoder.feed(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .as_bytes(),
+        );
+        let mut decompressor = None;
+        let mut decoded = Vec::new();
+        for chunk in body.chunks(7) {
+            decoder.feed(chunk);
+            while let Some(event) = decoder.decode().unwrap() {
+                match event {
+                    Event::Head(response) => {
+                        let encoding = response.headers().get("Content-Encoding").unwrap();
+                        decompressor = Some(Decompressor::new(&encoding).unwrap());
+                    }
+                    Event::Body(data) => {
+                        let decompressor = decompressor.as_mut().unwrap();
+                        decoded.extend_from_slice(&decompressor.feed(&data).unwrap());
+                    }
+                    Event::End => (),
+                }
+            }
+        }
+        decoded.extend_from_slice(&decompressor.unwrap().finish().unwrap());
+
+        assert_eq!(decoded, text.as_bytes());
+    }
+
+    #[cfg(all(feature = "brotli", feature = "zstd"))]
+    #[test]
+    fn decode_brotli_zstd() {
+        const BROTLI_HELLO: &[u8] = &[0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03];
+
+        let body = zstd::stream::encode_all(BROTLI_HELLO, 0).unwrap();
+        assert_eq!(decode("br, zstd", &body), Ok(Bytes::from("hello")));
+        assert!(decode("br", &BROTLI_HELLO[..4]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::method::Method;
+use crate::typed::Challenge;
+
+static CNONCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    pub fn parse(value: &str) -> Option<Algorithm> {
+        match value.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Algorithm::Md5),
+            "MD5-SESS" => Some(Algorithm::Md5Sess),
+            "SHA-256" => Some(Algorithm::Sha256),
+            "SHA-256-SESS" => Some(Algorithm::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Md5Sess => "MD5-sess",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_sess(&self) -> bool {
+        matches!(self, Algorithm::Md5Sess | Algorithm::Sha256Sess)
+    }
+
+    fn is_sha256(&self) -> bool {
+        matches!(self, Algorithm::Sha256 | Algorithm::Sha256Sess)
+    }
+
+    fn hash(&self, data: &[u8]) -> String {
+        if self.is_sha256() {
+            format!("{:x}", Sha256::digest(data))
+        } else {
+            format!("{:x}", Md5::digest(data))
+        }
+    }
+}
+
+/// Credentials answering `Digest` challenges (RFC 7616). The nonce count is
+/// kept per nonce, so a server reusing its nonce sees increasing counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestAuth {
+    username: String,
+    password: String,
+    nonce: String,
+    nc: u32,
+}
+
+impl DigestAuth {
+    pub fn new(username: &str, password: &str) -> DigestAuth {
+        DigestAuth {
+            username: username.to_string(),
+            password: password.to_string(),
+            nonce: String::new(),
+            nc: 0,
+        }
+    }
+
+    /// Picks the `Digest` challenge with the strongest supported algorithm.
+    pub fn select(challenges: &[Challenge]) -> Option<&Challenge> {
+        challenges
+            .iter()
+            .filter(|challenge| challenge.scheme.eq_ignore_ascii_case("Digest"))
+            .filter_map(|challenge| Some((challenge, algorithm(challenge)?)))
+            .max_by_key(|(_, algorithm)| algorithm.is_sha256())
+            .map(|(challenge, _)| challenge)
+    }
+
+    /// Value of the `Authorization` or `Proxy-Authorization` header answering
+    /// `challenge` for a request to `uri`, the request target as sent. The
+    /// body is only hashed with `qop=auth-int`.
+    pub fn respond(
+        &mut self,
+        challenge: &Challenge,
+        method: &Method,
+        uri: &str,
+        body: &[u8],
+    ) -> Result<String> {
+        let cnonce = new_cnonce();
+        self.respond_with_cnonce(challenge, method, uri, body, &cnonce)
+    }
+
+    fn respond_with_cnonce(
+        &mut self,
+        challenge: &Challenge,
+        method: &Method,
+        uri: &str,
+        body: &[u8],
+        cnonce: &str,
+    ) -> Result<String> {
+        let unsupported = || Error::AuthChallenge(challenge.scheme.clone());
+        let algorithm = algorithm(challenge).ok_or_else(unsupported)?;
+        let realm = challenge.realm().ok_or_else(unsupported)?;
+        let nonce = challenge.get_param("nonce").ok_or_else(unsupported)?;
+        let qop = match challenge.get_param("qop") {
+            Some(offered) => {
+                let offered: Vec<&str> = offered.split(',').map(str::trim).collect();
+                if offered.contains(&"auth") {
+                    Some("auth")
+                } else if offered.contains(&"auth-int") {
+                    Some("auth-int")
+                } else {
+                    return Err(unsupported());
+                }
+            }
+            None => None,
+        };
+        if self.nonce == nonce {
+            self.nc += 1;
+        } else {
+            self.nonce = nonce.to_string();
+            self.nc = 1;
+        }
+        let nc = format!("{:08x}", self.nc);
+
+        let mut ha1 =
+            algorithm.hash(format!("{}:{}:{}", self.username, realm, self.password).as_bytes());
+        if algorithm.is_sess() {
+            ha1 = algorithm.hash(format!("{}:{}:{}", ha1, nonce, cnonce).as_bytes());
+        }
+        let ha2 = match qop {
+            Some("auth-int") => algorithm
+                .hash(format!("{}:{}:{}", method.as_str(), uri, algorithm.hash(body)).as_bytes()),
+            _ => algorithm.hash(format!("{}:{}", method.as_str(), uri).as_bytes()),
+        };
+        let response = match qop {
+            Some(qop) => algorithm
+                .hash(format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2).as_bytes()),
+            None => algorithm.hash(format!("{}:{}:{}", ha1, nonce, ha2).as_bytes()),
+        };
+
+        let userhash = challenge
+            .get_param("userhash")
+            .map(|userhash| userhash.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let mut value = if userhash {
+            let username = algorithm.hash(format!("{}:{}", self.username, realm).as_bytes());
+            format!("Digest username=\"{}\"", username)
+        } else if self.username.is_ascii() && !self.username.contains('"') {
+            format!(
+                "Digest username=\"{}\"",
+                self.username.replace('\\', "\\\\")
+            )
+        } else {
+            format!("Digest username*=UTF-8''{}", ext_value(&self.username))
+        };
+        value.push_str(&format!(
+            ", realm=\"{}\", uri=\"{}\", algorithm={}, nonce=\"{}\"",
+            escape(realm),
+            escape(uri),
+            algorithm.as_str(),
+            escape(nonce)
+        ));
+        if let Some(qop) = qop {
+            value.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+        }
+        value.push_str(&format!(", response=\"{}\"", response));
+        if let Some(opaque) = challenge.get_param("opaque") {
+            value.push_str(&format!(", opaque=\"{}\"", escape(opaque)));
+        }
+        if userhash {
+            value.push_str(", userhash=true");
+        }
+        Ok(value)
+    }
+}
+
+/// `None` for an algorithm that is not supported, MD5 when absent.
+fn algorithm(challenge: &Challenge) -> Option<Algorithm> {
+    match challenge.get_param("algorithm") {
+        Some(algorithm) => Algorithm::parse(algorithm),
+        None => Some(Algorithm::Md5),
+    }
+}
+
+/// 128 random bits in hex. `RandomState` keys come from the OS random source
+/// and SipHash keyed with them cannot be predicted without the keys.
+fn new_cnonce() -> String {
+    let count = CNONCE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    (0..2u8)
+        .map(|half| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_usize(count);
+            hasher.write_u8(half);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Percent-encoding of RFC 8187 for `username*`.
+fn ext_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typed::{Header, WwwAuthenticate};
+
+    const CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn rfc_challenges(algorithm: &str) -> WwwAuthenticate {
+        WwwAuthenticate::parse(&format!(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm={}, \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+            algorithm
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn digest_rfc7616_examples() {
+        let mut auth = DigestAuth::new("Mufasa", "Circle of Life");
+        let challenges = rfc_challenges("MD5");
+        let challenge = DigestAuth::select(&challenges.0).unwrap();
+        let value = auth
+            .respond_with_cnonce(challenge, &Method::GET, "/dir/index.html", b"", CNONCE)
+            .unwrap();
+        assert!(value.starts_with("Digest username=\"Mufasa\", realm=\"http-auth@example.org\""));
+        assert!(value.contains("qop=auth, nc=00000001,"));
+        assert!(value.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(value.contains("opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""));
+
+        let challenges = rfc_challenges("SHA-256");
+        let challenge = DigestAuth::select(&challenges.0).unwrap();
+        let value = auth
+            .respond_with_cnonce(challenge, &Method::GET, "/dir/index.html", b"", CNONCE)
+            .unwrap();
+        assert!(value.contains("nc=00000002,"));
+        let mut auth = DigestAuth::new("Mufasa", "Circle of Life");
+        let value = auth
+            .respond_with_cnonce(challenge, &Method::GET, "/dir/index.html", b"", CNONCE)
+            .unwrap();
+        assert!(value.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn digest_cnonce() {
+        let cnonce = new_cnonce();
+        assert_eq!(cnonce.len(), 32);
+        assert!(cnonce.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(new_cnonce(), cnonce);
+    }
+
+    #[test]
+    fn digest_select() {
+        let challenges = WwwAuthenticate::parse(
+            "Basic realm=\"a\", Digest realm=\"a\", nonce=\"n\", algorithm=MD5, \
+             Digest realm=\"a\", nonce=\"n\", algorithm=SHA-256, \
+             Digest realm=\"a\", nonce=\"n\", algorithm=SHA-512-256",
+        )
+        .unwrap();
+        assert_eq!(
+            DigestAuth::select(&challenges.0)
+                .and_then(|challenge| challenge.get_param("algorithm")),
+            Some("SHA-256")
+        );
+
+        let mut auth = DigestAuth::new("Mufasa", "Circle of Life");
+        let value = auth
+            .respond(&challenges.0[1], &Method::GET, "/", b"")
+            .unwrap();
+        assert!(!value.contains("qop="));
+        assert_eq!(
+            auth.respond(&Challenge::new("Digest"), &Method::GET, "/", b""),
+            Err(Error::AuthChallenge("Digest".to_string()))
+        );
+    }
+
+    #[test]
+    fn digest_auth_int_userhash() {
+        let plain = Challenge::new("Digest")
+            .param("realm", "api@example.org")
+            .param("nonce", "5TsQWLVdgBdmrQ0XsxbDODV+57QdFR34I9HAbC/RVvkK")
+            .param("qop", "auth-int")
+            .param("algorithm", "SHA-256-sess");
+        let challenge = plain.clone().param("userhash", "true");
+        let mut auth = DigestAuth::new("J\u{e4}s\u{f8}n Doe", "Secret, or not?");
+        let value = auth
+            .respond_with_cnonce(&challenge, &Method::POST, "/doe.json", b"{}", CNONCE)
+            .unwrap();
+        assert!(value.starts_with(&format!(
+            "Digest username=\"{}\"",
+            Algorithm::Sha256.hash("J\u{e4}s\u{f8}n Doe:api@example.org".as_bytes())
+        )));
+        assert!(value.contains("qop=auth-int,"));
+        assert!(value.ends_with(", userhash=true"));
+
+        let mut auth = DigestAuth::new("J\u{e4}s\u{f8}n Doe", "Secret, or not?");
+        let value = auth
+            .respond_with_cnonce(&plain, &Method::POST, "/doe.json", b"{}", CNONCE)
+            .unwrap();
+        assert!(value.starts_with("Digest username*=UTF-8''J%C3%A4s%C3%B8n%20Doe"));
+    }
+}
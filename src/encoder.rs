@@ -0,0 +1,171 @@
+use bytes::{BufMut, BytesMut};
+
+use crate::decoder::is_chunked;
+use crate::headers::Headers;
+use crate::request::Request;
+use crate::response::Response;
+
+/// HTTP/1.x request encoder writing into a caller-owned buffer. When the
+/// request carries `Transfer-Encoding: chunked` the body is framed in chunks.
+#[derive(Debug, Default)]
+pub struct RequestEncoder {
+    chunked: bool,
+}
+
+impl RequestEncoder {
+    pub fn new() -> RequestEncoder {
+        RequestEncoder { chunked: false }
+    }
+
+    pub fn encode(&mut self, request: &Request, dst: &mut BytesMut) {
+        self.encode_head(request, dst);
+        if let Some(body) = request.get_body() {
+            self.encode_body(&body, dst);
+        }
+        self.encode_end(dst);
+    }
+
+    pub fn encode_head(&mut self, request: &Request, dst: &mut BytesMut) {
+        let headers = request.get_headers();
+        self.chunked = match headers.get("Transfer-Encoding") {
+            Some(value) => is_chunked(&value),
+            None => false,
+        };
+        dst.put_slice(request.request_line().as_bytes());
+        put_headers(&headers, dst);
+        dst.put_slice(b"\r\n");
+    }
+
+    pub fn encode_body(&mut self, chunk: &[u8], dst: &mut BytesMut) {
+        if !self.chunked {
+            dst.put_slice(chunk);
+        } else if !chunk.is_empty() {
+            dst.put_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            dst.put_slice(chunk);
+            dst.put_slice(b"\r\n");
+        }
+    }
+
+    pub fn encode_end(&mut self, dst: &mut BytesMut) {
+        if self.chunked {
+            dst.put_slice(b"0\r\n\r\n");
+        }
+    }
+}
+
+/// HTTP/1.x response encoder, the server-side counterpart of `RequestEncoder`.
+/// A `Content-Length` is added when the response has neither it nor chunked
+/// `Transfer-Encoding`, so the peer can find the end of the body.
+#[derive(Debug, Default)]
+pub struct ResponseEncoder {
+    chunked: bool,
+}
+
+impl ResponseEncoder {
+    pub fn new() -> ResponseEncoder {
+        ResponseEncoder { chunked: false }
+    }
+
+    pub fn encode(&mut self, response: &Response, dst: &mut BytesMut) {
+        self.encode_head(response, dst);
+        self.encode_body(&response.body, dst);
+        self.encode_end(dst);
+    }
+
+    pub fn encode_head(&mut self, response: &Response, dst: &mut BytesMut) {
+        let code = response.status_code();
+        let headers = response.headers();
+        self.chunked = match headers.get("Transfer-Encoding") {
+            Some(value) => is_chunked(&value),
+            None => false,
+        };
+        dst.put_slice(
+            format!("{} {} {}\r\n", response.version(), code, response.reason()).as_bytes(),
+        );
+        put_headers(headers, dst);
+        let no_body = code.is_info() || code.is(|c| c == 204 || c == 304);
+        if !self.chunked && !no_body && headers.get("Content-Length").is_none() {
+            dst.put_slice(format!("content-length: {}\r\n", response.body.len()).as_bytes());
+        }
+        dst.put_slice(b"\r\n");
+    }
+
+    pub fn encode_body(&mut self, chunk: &[u8], dst: &mut BytesMut) {
+        if !self.chunked {
+            dst.put_slice(chunk);
+        } else if !chunk.is_empty() {
+            dst.put_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            dst.put_slice(chunk);
+            dst.put_slice(b"\r\n");
+        }
+    }
+
+    pub fn encode_end(&mut self, dst: &mut BytesMut) {
+        if self.chunked {
+            dst.put_slice(b"0\r\n\r\n");
+        }
+    }
+}
+
+/// Writes one line per value, so fields kept apart by `Headers::append` go out
+/// as repeated fields again.
+fn put_headers(headers: &Headers, dst: &mut BytesMut) {
+    for (key, values) in headers.iter() {
+        for value in values.split('\n') {
+            dst.put_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use uri::Uri;
+
+    #[test]
+    fn encoder_request() {
+        let uri: Uri = "http://api.ipify.org/path".parse().unwrap();
+        let mut request = Request::new(&uri, None);
+        request.header_remove("Connection");
+        request.body("hello");
+        let mut dst = BytesMut::new();
+        RequestEncoder::new().encode(&request, &mut dst);
+        let msg = String::from_utf8(dst.to_vec()).unwrap();
+
+        assert!(msg.starts_with("GET /path HTTP/1.1\r\n"));
+        assert!(msg.contains("host: api.ipify.org\r\n"));
+        assert!(msg.contains("content-length: 5\r\n"));
+        assert!(msg.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn encoder_chunked() {
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let mut request = Request::new(&uri, None);
+        request.header("Transfer-Encoding", "chunked");
+        let mut encoder = RequestEncoder::new();
+        let mut dst = BytesMut::new();
+        encoder.encode_head(&request, &mut dst);
+        dst.clear();
+        encoder.encode_body(b"hello", &mut dst);
+        encoder.encode_body(b"", &mut dst);
+        encoder.encode_body(b" world", &mut dst);
+        encoder.encode_end(&mut dst);
+
+        assert_eq!(&dst[..], &b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn encoder_response() {
+        let mut response = Response::from_header(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        response.body = Bytes::from("hello");
+        let mut dst = BytesMut::new();
+        ResponseEncoder::new().encode(&response, &mut dst);
+
+        assert_eq!(
+            &dst[..],
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"[..]
+        );
+    }
+}
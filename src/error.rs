@@ -1,7 +1,9 @@
-use std::{io, num, result, str};
+use std::{convert::Infallible, io, num, result, str};
 
 use thiserror::Error as ThisError;
 
+use crate::{socks4, socks5};
+
 pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Debug, ThisError)]
@@ -12,6 +14,8 @@ pub enum Error {
     WrongHttp,
     #[error("empty response")]
     EmptyResponse,
+    #[error("empty request")]
+    EmptyRequest,
     #[error("parse headers")]
     ParseHeaders,
     #[error("unknown method {0}")]
@@ -22,6 +26,8 @@ pub enum Error {
     UnsupportedVersion(String),
     #[error("bad status")]
     StatusErr,
+    #[error("bad request line")]
+    RequestLineErr,
     #[error("bad headers")]
     HeadersErr,
     #[error("io error")]
@@ -32,18 +38,75 @@ pub enum Error {
     FromUtf8(#[from] str::Utf8Error),
     #[error("uri")]
     UriError(#[from] uri::Error),
-    #[error("Socks5")]
-    Socks5(#[from] rsl::error::Error),
+    #[error("SOCKS5 {0}")]
+    Socks5(socks5::Reply),
+    #[error("SOCKS5 negotiation failed, {0}")]
+    Socks5Negotiation(socks5::Negotiation),
+    #[error("SOCKS5 cannot send host name {0}")]
+    Socks5Address(String),
+    #[error("SOCKS4 {0}")]
+    Socks4(socks4::Reply),
+    #[error("SOCKS4 cannot connect to IPv6 address {0}")]
+    Socks4Address(String),
+    #[error("{0} would be resolved locally while a proxy is configured")]
+    LocalDns(String),
     #[error("header incomplete")]
     HeaderIncomplete,
     #[error("header more when 1024")]
     HeaderToBig,
+    #[error("body incomplete")]
+    BodyIncomplete,
+    #[error("invalid chunk")]
+    InvalidChunk,
+    #[error("multipart part announced {0} bytes but its reader gave {1}")]
+    PartLength(u64, u64),
+    #[error("{0} bytes received after the end of the response")]
+    UnexpectedData(usize),
     #[error("invalid status code {0}")]
     InvalidStatusCode(u16),
     #[error("unsupported proxy scheme {0}")]
     UnsupportedProxyScheme(String),
+    #[error("invalid proxy in {0}")]
+    ProxyEnv(String),
+    #[error("unsupported content encoding {0}")]
+    UnsupportedEncoding(String),
+    #[error("connection pool closed")]
+    PoolClosed,
+    #[error("proxy hop {0} failed: {1}")]
+    ProxyHop(usize, Box<Error>),
+    #[error("proxy judge answered with status {0}")]
+    JudgeStatus(u16),
+    #[error("proxy judge did not echo the client address")]
+    JudgeAddress,
+    #[error("no proxy available in the pool")]
+    NoProxyAvailable,
+    #[error("proxy tunnel failed with status {0}")]
+    TunnelFailed(u16),
+    #[error("invalid {0} header")]
+    InvalidHeader(String),
+    #[error("proxy authentication required, {scheme} challenge")]
+    ProxyAuthRequired {
+        scheme: String,
+        realm: Option<String>,
+    },
+    #[error("unsupported {0} authentication challenge")]
+    AuthChallenge(String),
+    #[error("invalid cookie file entry {0}")]
+    CookieFile(usize),
+    #[error("json error at line {line} column {column}: {message} near `{snippet}`")]
+    Json {
+        message: String,
+        line: usize,
+        column: usize,
+        snippet: String,
+    },
+    #[error("form encoding failed, {0}")]
+    Form(String),
     #[error("InvalidDNSNameError")]
     InvalidDNSNameError(#[from] tokio_rustls::webpki::InvalidDNSNameError),
+    #[cfg(feature = "http")]
+    #[error("http")]
+    Http(#[from] http::Error),
 }
 
 impl PartialEq for Error {
@@ -52,6 +115,7 @@ impl PartialEq for Error {
             (Error::EmptyUri, Error::EmptyUri) => true,
             (Error::WrongHttp, Error::WrongHttp) => true,
             (Error::EmptyResponse, Error::EmptyResponse) => true,
+            (Error::EmptyRequest, Error::EmptyRequest) => true,
             (Error::ParseHeaders, Error::ParseHeaders) => true,
             (Error::UnknownMethod(method), Error::UnknownMethod(other_method)) => {
                 method == other_method
@@ -63,6 +127,7 @@ impl PartialEq for Error {
                 version == other_version
             }
             (Error::StatusErr, Error::StatusErr) => true,
+            (Error::RequestLineErr, Error::RequestLineErr) => true,
             (Error::HeadersErr, Error::HeadersErr) => true,
             (Error::IO(io), Error::IO(other_io)) => io.to_string() == other_io.to_string(),
             (Error::ParseInt(int), Error::ParseInt(other_int)) => int == other_int,
@@ -70,11 +135,17 @@ impl PartialEq for Error {
             (Error::UriError(uri), Error::UriError(other_uri)) => {
                 uri.to_string() == other_uri.to_string()
             }
-            (Error::Socks5(socks), Error::Socks5(other_socks)) => {
-                socks.to_string() == other_socks.to_string()
-            }
+            (Error::Socks5(reply), Error::Socks5(other_reply)) => reply == other_reply,
+            (
+                Error::Socks5Negotiation(negotiation),
+                Error::Socks5Negotiation(other_negotiation),
+            ) => negotiation == other_negotiation,
+            (Error::Socks5Address(host), Error::Socks5Address(other_host)) => host == other_host,
             (Error::HeaderIncomplete, Error::HeaderIncomplete) => true,
             (Error::HeaderToBig, Error::HeaderToBig) => true,
+            (Error::BodyIncomplete, Error::BodyIncomplete) => true,
+            (Error::InvalidChunk, Error::InvalidChunk) => true,
+            (Error::UnexpectedData(len), Error::UnexpectedData(other_len)) => len == other_len,
             (Error::InvalidStatusCode(code), Error::InvalidStatusCode(other_code)) => {
                 code == other_code
             }
@@ -82,10 +153,65 @@ impl PartialEq for Error {
                 Error::UnsupportedProxyScheme(scheme),
                 Error::UnsupportedProxyScheme(other_scheme),
             ) => scheme == other_scheme,
+            (Error::UnsupportedEncoding(encoding), Error::UnsupportedEncoding(other_encoding)) => {
+                encoding == other_encoding
+            }
+            (Error::PartLength(len, read), Error::PartLength(other_len, other_read)) => {
+                len == other_len && read == other_read
+            }
+            (Error::ProxyEnv(name), Error::ProxyEnv(other_name)) => name == other_name,
+            (Error::PoolClosed, Error::PoolClosed) => true,
+            (Error::Socks4(reply), Error::Socks4(other_reply)) => reply == other_reply,
+            (Error::Socks4Address(addr), Error::Socks4Address(other_addr)) => addr == other_addr,
+            (Error::LocalDns(host), Error::LocalDns(other_host)) => host == other_host,
+            (Error::ProxyHop(hop, err), Error::ProxyHop(other_hop, other_err)) => {
+                hop == other_hop && err == other_err
+            }
+            (Error::JudgeStatus(code), Error::JudgeStatus(other_code)) => code == other_code,
+            (Error::JudgeAddress, Error::JudgeAddress) => true,
+            (Error::NoProxyAvailable, Error::NoProxyAvailable) => true,
+            (Error::TunnelFailed(code), Error::TunnelFailed(other_code)) => code == other_code,
+            (Error::InvalidHeader(name), Error::InvalidHeader(other_name)) => name == other_name,
+            (
+                Error::ProxyAuthRequired { scheme, realm },
+                Error::ProxyAuthRequired {
+                    scheme: other_scheme,
+                    realm: other_realm,
+                },
+            ) => scheme == other_scheme && realm == other_realm,
+            (Error::AuthChallenge(scheme), Error::AuthChallenge(other_scheme)) => {
+                scheme == other_scheme
+            }
+            (Error::CookieFile(entry), Error::CookieFile(other_entry)) => entry == other_entry,
+            (
+                Error::Json {
+                    message,
+                    line,
+                    column,
+                    ..
+                },
+                Error::Json {
+                    message: other_message,
+                    line: other_line,
+                    column: other_column,
+                    ..
+                },
+            ) => message == other_message && line == other_line && column == other_column,
+            (Error::Form(message), Error::Form(other_message)) => message == other_message,
             (Error::InvalidDNSNameError(dns), Error::InvalidDNSNameError(other_dns)) => {
                 dns == other_dns
             }
+            #[cfg(feature = "http")]
+            (Error::Http(http), Error::Http(other_http)) => {
+                http.to_string() == other_http.to_string()
+            }
             _ => false,
         }
     }
 }
+
+impl From<Infallible> for Error {
+    fn from(never: Infallible) -> Error {
+        match never {}
+    }
+}
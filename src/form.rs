@@ -0,0 +1,135 @@
+use uri::Uri;
+
+use crate::error::Result;
+
+/// Encodes `pairs` as `application/x-www-form-urlencoded`, which is also the
+/// usual format of a query string.
+pub fn encode_pairs<K, V>(pairs: &[(K, V)]) -> String
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    pairs
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                encode_component(key.as_ref()),
+                encode_component(value.as_ref())
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// Percent-encodes everything except alphanumerics and `*-._`, a space becomes
+/// `+`.
+pub fn encode_component(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                encoded.push(byte as char)
+            }
+            b' ' => encoded.push('+'),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Adds `query` to the query string of `uri`, in front of any fragment.
+pub fn append_query(uri: &Uri, query: &str) -> Result<Uri> {
+    if query.is_empty() {
+        return Ok(uri.clone());
+    }
+    let value = uri.as_str();
+    let (head, fragment) = match value.find('#') {
+        Some(pos) => value.split_at(pos),
+        None => (value, ""),
+    };
+    let separator = match head.find('?') {
+        Some(pos) if pos + 1 == head.len() || head.ends_with('&') => "",
+        Some(_) => "&",
+        None => "?",
+    };
+    Ok(format!("{}{}{}{}", head, separator, query, fragment).parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_builder::ClientBuilder;
+    #[cfg(feature = "serde")]
+    use crate::error::Error;
+
+    #[test]
+    fn form_encode_pairs() {
+        assert_eq!(
+            encode_pairs(&[("name", "a b&c"), ("city", "Zürich"), ("x", "1+1=2")]),
+            "name=a+b%26c&city=Z%C3%BCrich&x=1%2B1%3D2"
+        );
+        assert_eq!(encode_pairs::<&str, &str>(&[]), "");
+    }
+
+    #[test]
+    fn form_append_query() {
+        let uri: Uri = "http://api.ipify.org/path".parse().unwrap();
+        let uri = append_query(&uri, "a=1").unwrap();
+        assert_eq!(uri.abs_path(), "/path?a=1");
+        let uri = append_query(&uri, "b=2").unwrap();
+        assert_eq!(uri.abs_path(), "/path?a=1&b=2");
+    }
+
+    #[test]
+    fn client_builder_form_query() {
+        assert_eq!(
+            ClientBuilder::new().form(&[("a", "1 2")]),
+            ClientBuilder::new()
+                .body("a=1+2")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+        );
+        assert_eq!(
+            ClientBuilder::new()
+                .query(&[("a", "1")])
+                .query(&[("b", "2")]),
+            ClientBuilder::new().query(&[("a", "1"), ("b", "2")])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn client_builder_form_serde() {
+        use serde_crate::Serialize;
+
+        #[derive(Serialize)]
+        #[serde(crate = "serde_crate")]
+        struct Search {
+            q: String,
+            page: u32,
+        }
+
+        let search = Search {
+            q: "a b".to_string(),
+            page: 2,
+        };
+        assert_eq!(
+            ClientBuilder::new().form_serde(&search),
+            ClientBuilder::new().form(&[("q", "a b"), ("page", "2")])
+        );
+        assert_eq!(
+            ClientBuilder::new().query_serde(&search),
+            ClientBuilder::new().query(&[("q", "a b"), ("page", "2")])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn client_builder_form_serde_err() {
+        let nested = [("a", [1, 2])];
+        let result = ClientBuilder::new().form_serde(&nested).build().await;
+        assert!(matches!(result, Err(Error::Form(_))));
+        let result = ClientBuilder::new().query_serde(&nested).build().await;
+        assert!(matches!(result, Err(Error::Form(_))));
+    }
+}
@@ -5,6 +5,7 @@ use std::{
 };
 
 use crate::error::{Error, Result};
+use crate::typed::Header;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Headers(HashMap<String, String>);
@@ -38,6 +39,39 @@ impl Headers {
             .insert(key.to_string().to_lowercase(), val.to_string())
     }
 
+    /// Adds a value to a field that may already be set. Repeated fields are
+    /// joined with ", ", except `Set-Cookie` whose values may contain commas
+    /// and are kept one per line, see `get_all`.
+    pub fn append<T: ToString + ?Sized, U: ToString + ?Sized>(&mut self, key: &T, val: &U) {
+        let key = key.to_string().to_lowercase();
+        let separator = if key == "set-cookie" { "\n" } else { ", " };
+        let val = val.to_string();
+        self.0
+            .entry(key)
+            .and_modify(|value| {
+                value.push_str(separator);
+                value.push_str(&val);
+            })
+            .or_insert_with(|| val.clone());
+    }
+
+    /// Every value of a field kept apart by `append`.
+    pub fn get_all<T: ToString + ?Sized>(&self, k: &T) -> Vec<String> {
+        match self.0.get(&k.to_string().to_lowercase()) {
+            Some(value) => value.split('\n').map(|value| value.to_string()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Parsed value of the header `H`, `None` when it is missing or invalid.
+    pub fn typed<H: Header>(&self) -> Option<H> {
+        self.get(H::name()).and_then(|value| H::parse(&value).ok())
+    }
+
+    pub fn set_typed<H: Header>(&mut self, header: H) -> Option<String> {
+        self.insert(H::name(), &header.value())
+    }
+
     pub fn remove<T: ToString + ?Sized>(&mut self, key: &T) -> Option<String> {
         self.0.remove(&key.to_string().to_lowercase())
     }
@@ -63,19 +97,14 @@ impl FromStr for Headers {
         let headers = s.trim();
 
         if headers.lines().all(|e| e.contains(':')) {
-            let headers = headers
-                .lines()
-                .map(|elem| {
-                    let idx = elem.find(':').unwrap();
-                    let (key, value) = elem.split_at(idx);
-                    (
-                        key.to_string().to_lowercase(),
-                        value[1..].trim().to_string(),
-                    )
-                })
-                .collect();
-
-            Ok(Headers(headers))
+            let mut result = Headers::new();
+            for elem in headers.lines() {
+                let idx = elem.find(':').unwrap();
+                let (key, value) = elem.split_at(idx);
+                result.append(key, value[1..].trim());
+            }
+
+            Ok(result)
         } else {
             Err(Error::ParseHeaders)
         }
@@ -184,6 +213,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn headers_repeated() {
+        let headers = "Vary: Accept\r\n\
+                       Set-Cookie: a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+                       Vary: Cookie\r\n\
+                       Set-Cookie: b=2\r\n"
+            .parse::<Headers>()
+            .unwrap();
+
+        assert_eq!(headers.get("Vary"), Some("Accept, Cookie".to_string()));
+        assert_eq!(
+            headers.get_all("Set-Cookie"),
+            vec!["a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", "b=2"]
+        );
+        assert!(headers.get_all("Cookie").is_empty());
+    }
+
     #[test]
     fn headers_case_insensitive() {
         let header_names = ["Host", "host", "HOST", "HoSt"];
@@ -0,0 +1,308 @@
+use std::convert::{TryFrom, TryInto};
+
+use bytes::Bytes;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use uri::Uri;
+
+use crate::client::Client;
+use crate::client_builder::ClientBuilder;
+use crate::error::{Error, Result};
+use crate::headers::Headers;
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+use crate::status::{Status, StatusCode};
+use crate::version::Version;
+
+impl From<http::Method> for Method {
+    fn from(method: http::Method) -> Method {
+        match method.as_str().parse() {
+            Ok(method) => method,
+            Err(_) => Method::Custom(method.as_str().to_string()),
+        }
+    }
+}
+
+impl TryFrom<Method> for http::Method {
+    type Error = Error;
+
+    fn try_from(method: Method) -> Result<http::Method> {
+        http::Method::from_bytes(method.as_str().as_bytes())
+            .map_err(|_| Error::UnknownMethod(method.as_str().to_string()))
+    }
+}
+
+impl From<http::Version> for Version {
+    fn from(version: http::Version) -> Version {
+        match version {
+            http::Version::HTTP_09 => Version::Http09,
+            http::Version::HTTP_10 => Version::Http10,
+            http::Version::HTTP_2 => Version::H2,
+            http::Version::HTTP_3 => Version::H3,
+            _ => Version::Http11,
+        }
+    }
+}
+
+impl From<Version> for http::Version {
+    fn from(version: Version) -> http::Version {
+        match version {
+            Version::Http09 => http::Version::HTTP_09,
+            Version::Http10 => http::Version::HTTP_10,
+            Version::Http11 => http::Version::HTTP_11,
+            Version::H2 => http::Version::HTTP_2,
+            Version::H3 => http::Version::HTTP_3,
+        }
+    }
+}
+
+impl From<http::StatusCode> for StatusCode {
+    fn from(code: http::StatusCode) -> StatusCode {
+        StatusCode::from(code.as_u16())
+    }
+}
+
+impl TryFrom<StatusCode> for http::StatusCode {
+    type Error = Error;
+
+    fn try_from(code: StatusCode) -> Result<http::StatusCode> {
+        let code = u16::from(code);
+        http::StatusCode::from_u16(code).or(Err(Error::InvalidStatusCode(code)))
+    }
+}
+
+/// Repeated fields are combined with `Headers::append`.
+impl TryFrom<&HeaderMap> for Headers {
+    type Error = Error;
+
+    fn try_from(map: &HeaderMap) -> Result<Headers> {
+        let mut headers = Headers::with_capacity(map.keys_len());
+        for (key, value) in map.iter() {
+            let value = value.to_str().or(Err(Error::HeadersErr))?;
+            headers.append(key.as_str(), value);
+        }
+        Ok(headers)
+    }
+}
+
+impl TryFrom<&Headers> for HeaderMap {
+    type Error = Error;
+
+    fn try_from(headers: &Headers) -> Result<HeaderMap> {
+        let mut map = HeaderMap::with_capacity(headers.iter().len());
+        for (key, values) in headers.iter() {
+            let key = HeaderName::from_bytes(key.as_bytes()).or(Err(Error::HeadersErr))?;
+            for value in values.split('\n') {
+                let value = HeaderValue::from_str(value).or(Err(Error::HeadersErr))?;
+                map.append(&key, value);
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl TryFrom<Request> for http::Request<Bytes> {
+    type Error = Error;
+
+    fn try_from(request: Request) -> Result<http::Request<Bytes>> {
+        let mut http_request = http::Request::builder()
+            .method(http::Method::try_from(request.get_method())?)
+            .uri(request.request_uri())
+            .version(request.get_version().into())
+            .body(request.get_body().unwrap_or_default())?;
+        *http_request.headers_mut() = HeaderMap::try_from(&request.get_headers())?;
+        Ok(http_request)
+    }
+}
+
+/// The `http::Request` must carry an absolute URI, it is used for the `Host`
+/// header and the request target.
+impl<B: Into<Bytes>> TryFrom<http::Request<B>> for Request {
+    type Error = Error;
+
+    fn try_from(http_request: http::Request<B>) -> Result<Request> {
+        let (parts, body) = http_request.into_parts();
+        let uri: Uri = parts.uri.to_string().parse()?;
+        let mut request = Request::new(&uri, None);
+        request.method(parts.method.into());
+        request.version(parts.version.into());
+        request.headers(Headers::try_from(&parts.headers)?);
+        let body = body.into();
+        if !body.is_empty() {
+            request.body(body);
+        }
+        Ok(request)
+    }
+}
+
+impl TryFrom<Response> for http::Response<Bytes> {
+    type Error = Error;
+
+    fn try_from(response: Response) -> Result<http::Response<Bytes>> {
+        let version: Version = response.version().parse()?;
+        let mut http_response = http::Response::builder()
+            .status(http::StatusCode::try_from(response.status_code())?)
+            .version(version.into())
+            .body(response.body())?;
+        *http_response.headers_mut() = HeaderMap::try_from(response.headers())?;
+        Ok(http_response)
+    }
+}
+
+impl<B: Into<Bytes>> TryFrom<http::Response<B>> for Response {
+    type Error = Error;
+
+    fn try_from(http_response: http::Response<B>) -> Result<Response> {
+        let (parts, body) = http_response.into_parts();
+        let version = Version::from(parts.version);
+        let reason = parts.status.canonical_reason().unwrap_or("Unknown");
+        Ok(Response {
+            status: Status::from((version, parts.status.as_u16(), reason)),
+            headers: Headers::try_from(&parts.headers)?,
+            body: body.into(),
+            content_encoding: None,
+        })
+    }
+}
+
+impl ClientBuilder {
+    /// Takes method, URI, version, headers and body from an `http::Request`.
+    /// Repeated fields are combined, a value that is not valid UTF-8 fails
+    /// `build` with `Error::HeadersErr`.
+    pub fn http_request<B: Into<Bytes>>(self, request: http::Request<B>) -> ClientBuilder {
+        let (parts, body) = request.into_parts();
+        let body: Bytes = body.into();
+        let builder = self
+            .method(Method::from(parts.method))
+            .uri(parts.uri.to_string().as_str())
+            .version(Version::from(parts.version));
+        let builder = match Headers::try_from(&parts.headers) {
+            Ok(headers) => builder.headers(headers),
+            Err(err) => builder.fail(err),
+        };
+        if body.is_empty() {
+            builder
+        } else {
+            builder.body(body)
+        }
+    }
+}
+
+impl Client {
+    pub async fn send_http<B: Into<Bytes>>(
+        request: http::Request<B>,
+    ) -> Result<http::Response<Bytes>> {
+        let mut client = Client::builder().http_request(request).build().await?;
+        client.send().await?.try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_http() {
+        assert_eq!(
+            Method::from(http::Method::PATCH),
+            Method::Custom("PATCH".to_string())
+        );
+        assert_eq!(Method::from(http::Method::GET), Method::GET);
+        assert_eq!(http::Method::try_from(Method::POST), Ok(http::Method::POST));
+        assert!(http::Method::try_from(Method::Custom("BAD METHOD".to_string())).is_err());
+    }
+
+    #[test]
+    fn status_code_http() {
+        assert_eq!(
+            StatusCode::from(http::StatusCode::NOT_FOUND),
+            StatusCode::from(404)
+        );
+        assert_eq!(
+            http::StatusCode::try_from(StatusCode::from(200)),
+            Ok(http::StatusCode::OK)
+        );
+        assert!(http::StatusCode::try_from(StatusCode::from(1000)).is_err());
+    }
+
+    #[test]
+    fn headers_http() {
+        let mut map = HeaderMap::new();
+        map.append("Accept", HeaderValue::from_static("text/html"));
+        map.append("Accept", HeaderValue::from_static("text/plain"));
+        map.append("Host", HeaderValue::from_static("api.ipify.org"));
+        let headers = Headers::try_from(&map).unwrap();
+
+        assert_eq!(
+            headers.get("Accept"),
+            Some("text/html, text/plain".to_string())
+        );
+        assert_eq!(headers.get("Host"), Some("api.ipify.org".to_string()));
+        assert_eq!(HeaderMap::try_from(&headers).unwrap().len(), 2);
+
+        map.append("Bad", HeaderValue::from_bytes(b"\xff").unwrap());
+        assert_eq!(Headers::try_from(&map), Err(Error::HeadersErr));
+    }
+
+    #[test]
+    fn request_http() {
+        let http_request = http::Request::post("http://api.ipify.org/path?q=1")
+            .header("X-Test", "1")
+            .body("hello")
+            .unwrap();
+        let request = Request::try_from(http_request).unwrap();
+
+        assert_eq!(request.get_method(), Method::POST);
+        assert_eq!(request.request_uri(), "/path?q=1");
+        assert_eq!(request.get_headers().get("X-Test"), Some("1".to_string()));
+        assert_eq!(request.content_length(), 5);
+
+        let http_request = http::Request::try_from(request).unwrap();
+        assert_eq!(http_request.method(), http::Method::POST);
+        assert_eq!(http_request.uri(), "/path?q=1");
+        assert_eq!(http_request.headers()["host"], "api.ipify.org");
+        assert_eq!(http_request.body(), &Bytes::from("hello"));
+    }
+
+    #[tokio::test]
+    async fn client_builder_http_request() {
+        let http_request = http::Request::get("http://api.ipify.org/")
+            .header("Accept", "text/html")
+            .header("Accept", "text/plain")
+            .body(Bytes::new())
+            .unwrap();
+        assert_eq!(
+            Client::builder().http_request(http_request),
+            Client::builder()
+                .get("http://api.ipify.org/")
+                .header("Accept", "text/html, text/plain")
+        );
+
+        let http_request = http::Request::get("http://api.ipify.org/")
+            .header("Bad", HeaderValue::from_bytes(b"\xff").unwrap())
+            .body(Bytes::new())
+            .unwrap();
+        assert_eq!(
+            Client::builder()
+                .http_request(http_request)
+                .build()
+                .await
+                .unwrap_err(),
+            Error::HeadersErr
+        );
+    }
+
+    #[test]
+    fn response_http() {
+        let mut response =
+            Response::from_header(b"HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\n").unwrap();
+        response.body = Bytes::from("hello");
+        let http_response = http::Response::try_from(response.clone()).unwrap();
+
+        assert_eq!(http_response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(http_response.version(), http::Version::HTTP_11);
+        assert_eq!(http_response.headers()["content-length"], "5");
+        let from_http: Response = http_response.try_into().unwrap();
+        assert_eq!(from_http, response);
+    }
+}
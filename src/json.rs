@@ -0,0 +1,242 @@
+use std::marker::PhantomData;
+
+use bytes::{Buf, BytesMut};
+use serde_crate::de::DeserializeOwned;
+
+use crate::error::{Error, Result};
+use crate::response::Response;
+
+const SNIPPET_LEN: usize = 32;
+
+impl Response {
+    /// Deserializes the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).map_err(|err| json_error(err, &self.body))
+    }
+}
+
+/// Incremental deserializer for JSON values arriving in body chunks, e.g. from
+/// `Event::Body`. Consecutive values may be separated by whitespace, as in
+/// newline-delimited JSON. New input is only scanned for where the next value
+/// ends, it is parsed once, when complete.
+#[derive(Debug)]
+pub struct JsonDecoder<T> {
+    buf: BytesMut,
+    scan: Scan,
+    eof: bool,
+    item: PhantomData<fn() -> T>,
+}
+
+/// Progress of the scan for the end of the next top-level value in `buf`.
+#[derive(Debug, Default)]
+struct Scan {
+    pos: usize,
+    depth: usize,
+    started: bool,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Scan {
+    /// Scans `buf` from where the last call stopped, returns whether a
+    /// top-level value may have ended. A number or literal ends at the
+    /// whitespace after it.
+    fn complete(&mut self, buf: &[u8]) -> bool {
+        while let Some(&b) = buf.get(self.pos) {
+            self.pos += 1;
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => {
+                        self.in_string = false;
+                        if self.depth == 0 {
+                            return true;
+                        }
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            match b {
+                b'"' => {
+                    self.started = true;
+                    self.in_string = true;
+                }
+                b'{' | b'[' => {
+                    self.started = true;
+                    self.depth += 1;
+                }
+                b'}' | b']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return true;
+                    }
+                }
+                b' ' | b'\t' | b'\r' | b'\n' => {
+                    if self.started && self.depth == 0 {
+                        return true;
+                    }
+                }
+                _ => self.started = true,
+            }
+        }
+        false
+    }
+}
+
+impl<T: DeserializeOwned> JsonDecoder<T> {
+    pub fn new() -> JsonDecoder<T> {
+        JsonDecoder {
+            buf: BytesMut::new(),
+            scan: Scan::default(),
+            eof: false,
+            item: PhantomData,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Marks the end of the body, a value still incomplete after this is an
+    /// error.
+    pub fn finish(&mut self) {
+        self.eof = true;
+    }
+
+    /// Returns the next complete value, or `None` when more input is needed
+    /// or the body is done.
+    pub fn decode(&mut self) -> Result<Option<T>> {
+        if !self.eof && !self.scan.complete(&self.buf) {
+            return Ok(None);
+        }
+        let mut values = serde_json::Deserializer::from_slice(&self.buf).into_iter::<T>();
+        match values.next() {
+            Some(Ok(value)) => {
+                let offset = values.byte_offset();
+                self.buf.advance(offset);
+                self.scan = Scan::default();
+                Ok(Some(value))
+            }
+            Some(Err(err)) if err.is_eof() && !self.eof => Ok(None),
+            Some(Err(err)) => Err(json_error(err, &self.buf)),
+            None => {
+                self.buf.clear();
+                self.scan = Scan::default();
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Default for JsonDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds `Error::Json` with the part of `input` around the failing position.
+pub(crate) fn json_error(err: serde_json::Error, input: &[u8]) -> Error {
+    let offset = input
+        .split(|&b| b == b'\n')
+        .take(err.line().saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        + err.column();
+    let end = offset.min(input.len());
+    let start = end.saturating_sub(SNIPPET_LEN);
+    let end = (end + SNIPPET_LEN).min(input.len());
+    Error::Json {
+        message: err.to_string(),
+        line: err.line(),
+        column: err.column(),
+        snippet: String::from_utf8_lossy(&input[start..end]).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_builder::ClientBuilder;
+    use serde_crate::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    #[serde(crate = "serde_crate")]
+    struct Ip {
+        ip: String,
+    }
+
+    #[test]
+    fn response_json() {
+        let mut response = Response::from_header(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        response.body = r#"{"ip": "127.0.0.1"}"#.into();
+        assert_eq!(
+            response.json::<Ip>().unwrap(),
+            Ip {
+                ip: "127.0.0.1".to_string()
+            }
+        );
+
+        response.body = "{\n\"ip\": 127}".into();
+        match response.json::<Ip>().unwrap_err() {
+            Error::Json { line, snippet, .. } => {
+                assert_eq!(line, 2);
+                assert!(snippet.contains("127"));
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn client_builder_json() {
+        let ip = Ip {
+            ip: "127.0.0.1".to_string(),
+        };
+        assert_eq!(
+            ClientBuilder::new().json(&ip),
+            ClientBuilder::new().json_bytes(r#"{"ip":"127.0.0.1"}"#)
+        );
+
+        let mut map = std::collections::HashMap::new();
+        map.insert((1, 2), 3);
+        let result = ClientBuilder::new().json(&map).build().await;
+        assert!(matches!(result, Err(Error::Json { .. })));
+    }
+
+    #[test]
+    fn json_decoder() {
+        let mut decoder = JsonDecoder::<Ip>::new();
+        let mut values = Vec::new();
+        for chunk in br#"{"ip": "1.1.1.1"}
+{"ip": "8.8.8.8"}
+{"ip": "#
+            .chunks(5)
+        {
+            decoder.feed(chunk);
+            while let Some(value) = decoder.decode().unwrap() {
+                values.push(value.ip);
+            }
+        }
+        assert_eq!(values, vec!["1.1.1.1", "8.8.8.8"]);
+
+        decoder.finish();
+        assert!(decoder.decode().is_err());
+
+        let mut decoder = JsonDecoder::<Vec<String>>::new();
+        let value = format!("[{}\"a\\\"]\"]", r#""[{", "#.repeat(1000));
+        let mut values = Vec::new();
+        for chunk in value.as_bytes().chunks(3) {
+            decoder.feed(chunk);
+            values.extend(decoder.decode().unwrap());
+        }
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].len(), 1001);
+        assert_eq!(values[0][1000], "a\"]");
+        let mut decoder = JsonDecoder::<u32>::new();
+        decoder.feed(b"12");
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.feed(b"3\n");
+        assert_eq!(decoder.decode().unwrap(), Some(123));
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+use uri::Uri;
+
+use crate::cookie::CookieStore;
+use crate::decompress::{accept_encoding, decompress};
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::proxy_rule::ProxyRule;
+use crate::request::Request;
+use crate::response::Response;
+use crate::route::Routes;
+
+type ProxySelector = Arc<dyn Fn(&Request) -> Option<Uri> + Send + Sync>;
+
+/// Picks the proxy for every request, `None` sends it direct.
+#[derive(Clone)]
+pub struct SetProxyLayer {
+    select: ProxySelector,
+}
+
+impl SetProxyLayer {
+    pub fn new<F>(select: F) -> SetProxyLayer
+    where
+        F: Fn(&Request) -> Option<Uri> + Send + Sync + 'static,
+    {
+        SetProxyLayer {
+            select: Arc::new(select),
+        }
+    }
+
+    pub fn fixed(proxy: Uri) -> SetProxyLayer {
+        SetProxyLayer::new(move |_| Some(proxy.clone()))
+    }
+
+    /// Picks the proxy of every request with `rule`, e.g. the one
+    /// `ProxyRule::from_env()` reads.
+    pub fn rule(rule: ProxyRule) -> SetProxyLayer {
+        SetProxyLayer::new(move |request| rule.select(&request.get_uri()?))
+    }
+
+    /// Picks the proxy of every request from the routing table `routes`.
+    pub fn routes(routes: Routes) -> SetProxyLayer {
+        SetProxyLayer::new(move |request| routes.select(&request.get_uri()?))
+    }
+}
+
+impl<S> Layer<S> for SetProxyLayer {
+    type Service = SetProxy<S>;
+
+    fn layer(&self, inner: S) -> SetProxy<S> {
+        SetProxy {
+            inner,
+            select: self.select.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SetProxy<S> {
+    inner: S,
+    select: ProxySelector,
+}
+
+impl<S> Service<Request> for SetProxy<S>
+where
+    S: Service<Request>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> S::Future {
+        let proxy = (self.select)(&request);
+        request.proxy(proxy);
+        self.inner.call(request)
+    }
+}
+
+/// Adds headers the request does not set itself.
+#[derive(Debug, Clone)]
+pub struct HeaderDefaultsLayer {
+    headers: Headers,
+}
+
+impl HeaderDefaultsLayer {
+    pub fn new(headers: Headers) -> HeaderDefaultsLayer {
+        HeaderDefaultsLayer { headers }
+    }
+}
+
+impl<S> Layer<S> for HeaderDefaultsLayer {
+    type Service = HeaderDefaults<S>;
+
+    fn layer(&self, inner: S) -> HeaderDefaults<S> {
+        HeaderDefaults {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeaderDefaults<S> {
+    inner: S,
+    headers: Headers,
+}
+
+impl<S> Service<Request> for HeaderDefaults<S>
+where
+    S: Service<Request>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> S::Future {
+        let headers = request.get_headers();
+        for (key, value) in self.headers.iter() {
+            if headers.get(key).is_none() {
+                request.header(key, value);
+            }
+        }
+        self.inner.call(request)
+    }
+}
+
+/// Advertises the codings built into the crate and decodes response bodies.
+#[derive(Debug, Clone, Default)]
+pub struct DecompressionLayer;
+
+impl DecompressionLayer {
+    pub fn new() -> DecompressionLayer {
+        DecompressionLayer
+    }
+}
+
+impl<S> Layer<S> for DecompressionLayer {
+    type Service = Decompression<S>;
+
+    fn layer(&self, inner: S) -> Decompression<S> {
+        Decompression { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Decompression<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for Decompression<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Error: From<Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        if request.get_headers().get("Accept-Encoding").is_none() {
+            if let Some(accept) = accept_encoding() {
+                request.header("Accept-Encoding", &accept);
+            }
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = future.await?;
+            decompress(&mut response)?;
+            Ok(response)
+        })
+    }
+}
+
+/// Sends the cookies of a shared `CookieStore` and stores the `Set-Cookie`
+/// values of every response, redirects included when it sits below the layer
+/// that follows them.
+#[derive(Debug, Clone)]
+pub struct CookieLayer {
+    store: Arc<dyn CookieStore>,
+}
+
+impl CookieLayer {
+    pub fn new(store: Arc<dyn CookieStore>) -> CookieLayer {
+        CookieLayer { store }
+    }
+}
+
+impl<S> Layer<S> for CookieLayer {
+    type Service = Cookies<S>;
+
+    fn layer(&self, inner: S) -> Cookies<S> {
+        Cookies {
+            inner,
+            store: self.store.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cookies<S> {
+    inner: S,
+    store: Arc<dyn CookieStore>,
+}
+
+impl<S> Service<Request> for Cookies<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let uri = request.get_uri();
+        if let Some(cookies) = uri.as_ref().and_then(|uri| self.store.cookies(uri)) {
+            let cookies = match request.get_headers().get("Cookie") {
+                Some(own) => format!("{}; {}", own, cookies),
+                None => cookies,
+            };
+            request.header("Cookie", &cookies);
+        }
+        let store = self.store.clone();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await?;
+            if let Some(uri) = uri {
+                store.set_cookies(&uri, &response.headers().get_all("Set-Cookie"));
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::{service_fn, ServiceExt};
+
+    fn echo() -> impl Service<Request, Response = Request, Error = Error> {
+        service_fn(|request: Request| async move { Ok::<_, Error>(request) })
+    }
+
+    #[tokio::test]
+    async fn set_proxy_layer() {
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let proxy: Uri = "http://127.0.0.1:5858".parse().unwrap();
+        let service = SetProxyLayer::fixed(proxy.clone()).layer(echo());
+        let request = service.oneshot(Request::new(&uri, None)).await.unwrap();
+
+        assert_eq!(request.get_proxy(), Some(proxy));
+
+        let rule = ProxyRule::new()
+            .all("socks5h://127.0.0.1:1080")
+            .no_proxy("ipify.org");
+        let service = SetProxyLayer::rule(rule).layer(echo());
+        let request = service.oneshot(Request::new(&uri, None)).await.unwrap();
+        assert_eq!(request.get_proxy(), None);
+    }
+
+    #[tokio::test]
+    async fn header_defaults_layer() {
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let mut headers = Headers::new();
+        headers.insert("User-Agent", "netc");
+        headers.insert("Connection", "keep-alive");
+        let service = HeaderDefaultsLayer::new(headers).layer(echo());
+        let request = service.oneshot(Request::new(&uri, None)).await.unwrap();
+
+        assert_eq!(request.user_agent(), Some("netc".to_string()));
+        assert_eq!(
+            request.get_headers().get("Connection"),
+            Some("Close".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn decompression_layer() {
+        let service = DecompressionLayer::new().layer(service_fn(|_: Request| async {
+            let mut response =
+                Response::from_header(b"HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\n\r\n")?;
+            response.body = "hello".into();
+            Ok::<_, Error>(response)
+        }));
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let response = service.oneshot(Request::new(&uri, None)).await.unwrap();
+
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.text().unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn cookie_layer() {
+        use crate::cookie::Jar;
+
+        let jar = Arc::new(Jar::new());
+        let mut service =
+            CookieLayer::new(jar.clone()).layer(service_fn(|request: Request| async move {
+                let mut response = Response::from_header(
+                    b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2; Path=/\r\n\r\n",
+                )?;
+                response.body = request
+                    .get_headers()
+                    .get("Cookie")
+                    .unwrap_or_default()
+                    .into();
+                Ok::<_, Error>(response)
+            }));
+        let uri: Uri = "http://api.ipify.org/".parse().unwrap();
+        let response = (&mut service)
+            .oneshot(Request::new(&uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "");
+
+        let response = service.oneshot(Request::new(&uri, None)).await.unwrap();
+        assert_eq!(response.text().unwrap(), "a=1; b=2");
+        assert_eq!(jar.all().len(), 2);
+    }
+}
@@ -1,23 +1,73 @@
+pub mod auth;
+pub mod charset;
+pub mod checker;
 pub mod client;
 pub mod client_builder;
+#[cfg(feature = "codec")]
+pub mod codec;
+mod coding;
+pub mod compress;
+#[cfg(feature = "connector")]
+pub mod connector;
+pub mod cookie;
+pub mod cookie_file;
+pub mod decoder;
+pub mod decompress;
+pub mod digest;
+pub mod encoder;
 pub mod error;
+pub mod form;
 pub mod headers;
 pub mod http;
+#[cfg(feature = "http")]
+pub mod http_compat;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "tower")]
+pub mod layer;
 pub mod method;
+pub mod multipart;
+pub mod pool;
+pub mod proxy_pool;
+pub mod proxy_rule;
 pub mod request;
 pub mod response;
+pub mod route;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod socks4;
+pub mod socks5;
 pub mod status;
 pub mod stream;
+pub mod typed;
 pub mod version;
 
+pub use crate::auth::Authenticator;
+pub use crate::checker::{Anonymity, Checker, Report};
 pub use crate::client::Client;
 pub use crate::client_builder::ClientBuilder;
+#[cfg(feature = "codec")]
+pub use crate::codec::{ClientCodec, ServerCodec};
+#[cfg(feature = "connector")]
+pub use crate::connector::{Connector, ProxyStream};
+pub use crate::cookie::{Cookie, CookieStore, Jar};
+pub use crate::decoder::{Event, RequestDecoder, ResponseDecoder};
+pub use crate::encoder::{RequestEncoder, ResponseEncoder};
 pub use crate::error::{Error, Result};
 pub use crate::headers::Headers;
 pub use crate::http::HttpStream;
+#[cfg(feature = "serde")]
+pub use crate::json::JsonDecoder;
 pub use crate::method::Method;
+pub use crate::multipart::{Multipart, Part};
+pub use crate::pool::Pool;
+pub use crate::proxy_pool::{ProxyPool, Strategy};
+pub use crate::proxy_rule::{NoProxy, ProxyRule};
 pub use crate::request::Request;
 pub use crate::response::Response;
+pub use crate::route::{Match, Route, Routes};
+#[cfg(feature = "tower")]
+pub use crate::service::ClientService;
 pub use crate::status::{Status, StatusCode};
 pub use crate::stream::MaybeHttpsStream;
 pub use crate::version::Version;
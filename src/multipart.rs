@@ -0,0 +1,321 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::Path,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{Error, Result};
+use crate::headers::Headers;
+
+const CHUNK_SIZE: usize = 8192;
+
+static BOUNDARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// `multipart/form-data` body. Parts are produced one chunk at a time by
+/// `next_chunk`, so file and reader parts are never held in memory whole.
+pub struct Multipart {
+    boundary: String,
+    parts: VecDeque<Part>,
+    current: Option<Body>,
+    finished: bool,
+}
+
+impl Multipart {
+    pub fn new() -> Multipart {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos());
+        let count = BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed);
+        Multipart::with_boundary(&format!("netc-{:032x}-{:08x}", nanos, count))
+    }
+
+    pub fn with_boundary(boundary: &str) -> Multipart {
+        Multipart {
+            boundary: boundary.to_string(),
+            parts: VecDeque::new(),
+            current: None,
+            finished: false,
+        }
+    }
+
+    pub fn text<T: ToString + ?Sized>(self, name: &str, value: &T) -> Multipart {
+        self.part(name, Part::text(value))
+    }
+
+    pub fn bytes<B: Into<Bytes>>(self, name: &str, value: B) -> Multipart {
+        self.part(name, Part::bytes(value))
+    }
+
+    pub fn part(mut self, name: &str, mut part: Part) -> Multipart {
+        part.name = name.to_string();
+        self.parts.push_back(part);
+        self
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Value for the request `Content-Type`.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Length of the whole body, `None` when a reader part has no known size.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut len = self.closing().len() as u64;
+        for part in self.parts.iter() {
+            len += part.head(&self.boundary).len() as u64 + part.body.len()? + 2;
+        }
+        Some(len)
+    }
+
+    /// Returns the next piece of the body, `None` once it has been written
+    /// out completely. A reader part that ends before or goes past the length
+    /// it was given fails with `Error::PartLength`, as `Content-Length` would
+    /// no longer match the body.
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        match self.current.take() {
+            Some(Body::Bytes(body)) => {
+                let mut chunk = BytesMut::with_capacity(body.len() + 2);
+                chunk.extend_from_slice(&body);
+                chunk.extend_from_slice(b"\r\n");
+                return Ok(Some(chunk.freeze()));
+            }
+            Some(Body::Reader(mut reader, len, read)) => {
+                let mut buf = vec![0u8; CHUNK_SIZE];
+                let n = reader.read(&mut buf).await?;
+                let read = read + n as u64;
+                match len {
+                    Some(len) if read > len || (n == 0 && read < len) => {
+                        return Err(Error::PartLength(len, read))
+                    }
+                    _ if n == 0 => return Ok(Some(Bytes::from_static(b"\r\n"))),
+                    _ => (),
+                }
+                buf.truncate(n);
+                self.current = Some(Body::Reader(reader, len, read));
+                return Ok(Some(buf.into()));
+            }
+            None => (),
+        }
+        match self.parts.pop_front() {
+            Some(part) => {
+                let head = part.head(&self.boundary);
+                self.current = Some(part.body);
+                Ok(Some(head.into()))
+            }
+            None if !self.finished => {
+                self.finished = true;
+                Ok(Some(self.closing().into()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Collects the whole body, reading every reader part to the end.
+    pub async fn to_bytes(mut self) -> Result<Bytes> {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body.freeze())
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("boundary", &self.boundary)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+/// One field of a `Multipart` form.
+pub struct Part {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    headers: Headers,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Bytes),
+    /// Reader, announced length and bytes read so far.
+    Reader(Pin<Box<dyn AsyncRead + Send>>, Option<u64>, u64),
+}
+
+impl Body {
+    fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(body) => Some(body.len() as u64),
+            Body::Reader(_, len, _) => *len,
+        }
+    }
+}
+
+impl Part {
+    pub fn text<T: ToString + ?Sized>(value: &T) -> Part {
+        Part::new(Body::Bytes(value.to_string().into()))
+    }
+
+    pub fn bytes<B: Into<Bytes>>(value: B) -> Part {
+        Part::new(Body::Bytes(value.into()))
+    }
+
+    /// Part read from `reader`. Without `len` the form has no
+    /// `Content-Length` and is sent chunked.
+    pub fn reader<R>(reader: R, len: Option<u64>) -> Part
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Part::new(Body::Reader(Box::pin(reader), len, 0)).content_type("application/octet-stream")
+    }
+
+    /// Part streamed from the file at `path`, named after it.
+    pub async fn file<P: AsRef<Path>>(path: P) -> Result<Part> {
+        let path = path.as_ref();
+        let file = File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let part = Part::reader(file, Some(len));
+        Ok(match path.file_name() {
+            Some(name) => part.file_name(&name.to_string_lossy()),
+            None => part,
+        })
+    }
+
+    pub fn file_name(mut self, file_name: &str) -> Part {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Part {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn header<T: ToString + ?Sized, U: ToString + ?Sized>(
+        mut self,
+        key: &T,
+        value: &U,
+    ) -> Part {
+        self.headers.insert(key, value);
+        self
+    }
+
+    fn new(body: Body) -> Part {
+        Part {
+            name: String::new(),
+            file_name: None,
+            content_type: None,
+            headers: Headers::new(),
+            body,
+        }
+    }
+
+    fn head(&self, boundary: &str) -> String {
+        let mut head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            boundary,
+            escape(&self.name)
+        );
+        if let Some(file_name) = &self.file_name {
+            head.push_str(&format!("; filename=\"{}\"", escape(file_name)));
+        }
+        head.push_str("\r\n");
+        if let Some(content_type) = &self.content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        for (key, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+impl fmt::Debug for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("name", &self.name)
+            .field("file_name", &self.file_name)
+            .field("content_type", &self.content_type)
+            .field("headers", &self.headers)
+            .field("len", &self.body.len())
+            .finish()
+    }
+}
+
+/// Quotes, CR and LF are percent-encoded in names, as browsers do.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn multipart_body() {
+        let form = Multipart::with_boundary("xyz").text("name", "netc").part(
+            "file",
+            Part::reader(&b"hello"[..], Some(5))
+                .file_name("a\"b.txt")
+                .content_type("text/plain"),
+        );
+        assert_eq!(form.content_type(), "multipart/form-data; boundary=xyz");
+        let len = form.content_length();
+        let body = form.to_bytes().await.unwrap();
+
+        assert_eq!(
+            &body[..],
+            &b"--xyz\r\n\
+               Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+               netc\r\n\
+               --xyz\r\n\
+               Content-Disposition: form-data; name=\"file\"; filename=\"a%22b.txt\"\r\n\
+               Content-Type: text/plain\r\n\r\n\
+               hello\r\n\
+               --xyz--\r\n"[..]
+        );
+        assert_eq!(len, Some(body.len() as u64));
+    }
+
+    #[tokio::test]
+    async fn multipart_unknown_length() {
+        let form = Multipart::new().part("data", Part::reader(&b"hello"[..], None));
+        assert_eq!(form.content_length(), None);
+        assert_ne!(Multipart::new().boundary(), form.boundary());
+    }
+
+    #[tokio::test]
+    async fn multipart_reader_length() {
+        let form = Multipart::new().part("data", Part::reader(&b"hello"[..], Some(3)));
+        assert_eq!(form.to_bytes().await, Err(Error::PartLength(3, 5)));
+
+        let form = Multipart::new().part("data", Part::reader(&b"hello"[..], Some(8)));
+        assert_eq!(form.to_bytes().await, Err(Error::PartLength(8, 5)));
+    }
+}
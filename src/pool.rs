@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    io, result,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tokio::sync::Semaphore;
+use tokio::time;
+use uri::Uri;
+
+use crate::auth::{proxy_auth_required, Authenticator, Credentials, Target};
+use crate::decoder::{is_chunked, ResponseDecoder};
+use crate::error::{Error, Result};
+use crate::method::Method;
+use crate::proxy_pool::{is_proxy_error, ProxyPool};
+use crate::request::Request;
+use crate::response::Response;
+use crate::route::{Route, Routes};
+use crate::stream::{check_no_local_dns, MaybeHttpsStream};
+
+const MAX_CONNECTIONS: usize = 100;
+const MAX_IDLE_PER_HOST: usize = 8;
+
+/// Keep-alive connection pool shared by clones. Idle connections are keyed by
+/// target and proxy, the semaphore bounds the number of requests in flight.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+    no_local_dns: bool,
+    routes: Option<Routes>,
+    proxies: Option<ProxyPool>,
+    preemptive_proxy_auth: bool,
+}
+
+#[derive(Debug)]
+struct Inner {
+    idle: Mutex<HashMap<String, Vec<MaybeHttpsStream>>>,
+    /// Credentials of each proxy URI, kept so a `Digest` nonce is answered
+    /// again without a new 407.
+    proxy_auth: Mutex<HashMap<String, Arc<Credentials>>>,
+    semaphore: Arc<Semaphore>,
+    max_idle_per_host: usize,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new(MAX_CONNECTIONS, MAX_IDLE_PER_HOST)
+    }
+}
+
+impl Pool {
+    pub fn new(max_connections: usize, max_idle_per_host: usize) -> Pool {
+        Pool {
+            inner: Arc::new(Inner {
+                idle: Mutex::new(HashMap::new()),
+                proxy_auth: Mutex::new(HashMap::new()),
+                semaphore: Arc::new(Semaphore::new(max_connections)),
+                max_idle_per_host,
+            }),
+            no_local_dns: false,
+            routes: None,
+            proxies: None,
+            preemptive_proxy_auth: false,
+        }
+    }
+
+    /// Fails requests with `Error::LocalDns` rather than resolving their
+    /// host name here while they go through a proxy.
+    pub fn no_local_dns(mut self) -> Pool {
+        self.no_local_dns = true;
+        self
+    }
+
+    /// Sends requests without a proxy of their own along the route `routes`
+    /// picks for their target, failing over within the pool of a `Route::Pool`
+    /// as `proxies` does.
+    pub fn routes(mut self, routes: Routes) -> Pool {
+        self.routes = Some(routes);
+        self
+    }
+
+    /// Sends requests without a proxy of their own through a proxy picked
+    /// from `proxies` when no routes are set. A proxy error, such as a refused
+    /// connection or one reset after the request was written, is marked as a
+    /// failure of the proxy. Another proxy is tried, once the request was
+    /// written for idempotent methods only. Responses, HTTP errors included,
+    /// are returned.
+    pub fn proxies(mut self, proxies: ProxyPool) -> Pool {
+        self.proxies = Some(proxies);
+        self
+    }
+
+    /// Sends the credentials of a proxy URI as `Basic` before any 407,
+    /// saving a round trip with proxies known to want them.
+    pub fn preemptive_proxy_auth(mut self) -> Pool {
+        self.preemptive_proxy_auth = true;
+        self
+    }
+
+    pub fn semaphore(&self) -> Arc<Semaphore> {
+        self.inner.semaphore.clone()
+    }
+
+    pub fn idle(&self) -> usize {
+        match self.inner.idle.lock() {
+            Ok(idle) => idle.values().map(|streams| streams.len()).sum(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Sends `request` to its target over an idle connection when there is
+    /// one, otherwise over a new one, asking for `Connection: keep-alive`
+    /// in place of the default `Connection: Close`. A failed idle connection
+    /// is retried once on a fresh connection for idempotent methods. A proxy
+    /// asking for credentials gets those of its URI, a 407 left unanswered
+    /// fails with `Error::ProxyAuthRequired`. The scheme and `Digest` nonce a
+    /// proxy settled on are reused by later requests through it.
+    pub async fn send(&self, request: &Request) -> Result<Response> {
+        let result = match self.route(request) {
+            Some(Route::Pool(proxies)) => self.send_rotating(&proxies, request).await,
+            Some(Route::Proxy(proxy)) => {
+                let mut request = request.clone();
+                request.proxy(Some(proxy));
+                self.send_proxied(&request).await
+            }
+            Some(Route::Direct) => self.send_proxied(request).await,
+            None => match &self.proxies {
+                Some(proxies) if request.get_proxy().is_none() => {
+                    self.send_rotating(proxies, request).await
+                }
+                _ => self.send_proxied(request).await,
+            },
+        };
+        result.map_err(|failed| failed.err)
+    }
+
+    /// Sends `request` through proxies of `proxies` in turn until one answers.
+    /// A proxy error counts as a failure of the proxy. Before the request was
+    /// written another proxy is tried whatever the method, as the origin never
+    /// saw the request, after that only for idempotent methods. The last error
+    /// is returned once `max_attempts` proxies were tried or none is left.
+    async fn send_rotating(
+        &self,
+        proxies: &ProxyPool,
+        request: &Request,
+    ) -> result::Result<Response, Failed> {
+        let uri = request.get_uri().ok_or(Error::EmptyUri)?;
+        let mut tried = Vec::new();
+        let mut last = None;
+        loop {
+            let proxy = match proxies.pick_except(&uri, &tried) {
+                Some(proxy) => proxy,
+                None => return Err(last.unwrap_or_else(|| Error::NoProxyAvailable.into())),
+            };
+            let mut attempt = request.clone();
+            attempt.proxy(Some(proxy.clone()));
+            let start = Instant::now();
+            let sent = self.send_proxied(&attempt);
+            let sent = match proxies.attempt_timeout() {
+                Some(timeout) => time::timeout(timeout, sent)
+                    .await
+                    .unwrap_or_else(|_| Err(Failed::written(timed_out()))),
+                None => sent.await,
+            };
+            let failed = match sent {
+                Ok(response) => {
+                    proxies.mark_ok(&proxy, start.elapsed());
+                    return Ok(response);
+                }
+                Err(failed) => failed,
+            };
+            let proxy_error = is_proxy_error(&failed.err);
+            if proxy_error {
+                proxies.mark_failed(&proxy);
+            }
+            let retry = if failed.written {
+                is_idempotent(&request.get_method())
+            } else {
+                proxy_error
+            };
+            tried.push(proxy);
+            if !retry || tried.len() >= proxies.attempts() {
+                return Err(failed);
+            }
+            last = Some(failed);
+        }
+    }
+
+    async fn send_proxied(&self, request: &Request) -> result::Result<Response, Failed> {
+        let credentials = self.proxy_credentials(request);
+        let mut request = request.clone();
+        if let Some(credentials) = &credentials {
+            credentials.before_send(&mut request, Target::Proxy)?;
+        }
+        let response = self.send_once(&request).await?;
+        if u16::from(response.status_code()) != 407 || !request.forwarded() {
+            return Ok(response);
+        }
+        let response = match credentials {
+            Some(credentials)
+                if credentials.on_challenge(&mut request, &response, Target::Proxy)? =>
+            {
+                credentials.before_send(&mut request, Target::Proxy)?;
+                self.send_once(&request).await?
+            }
+            _ => response,
+        };
+        match u16::from(response.status_code()) {
+            407 => Err(proxy_auth_required(&response).into()),
+            _ => Ok(response),
+        }
+    }
+
+    /// Credentials shared by every request forwarded by the proxy of
+    /// `request`, `None` when it has none in its URI.
+    fn proxy_credentials(&self, request: &Request) -> Option<Arc<Credentials>> {
+        if !request.forwarded() {
+            return None;
+        }
+        let proxy = request.get_proxy()?;
+        let mut proxy_auth = self.inner.proxy_auth.lock().ok()?;
+        if let Some(credentials) = proxy_auth.get(proxy.as_str()) {
+            return Some(credentials.clone());
+        }
+        let credentials = Credentials::from_uri(&proxy)?;
+        let credentials = Arc::new(if self.preemptive_proxy_auth {
+            credentials.preemptive()
+        } else {
+            credentials
+        });
+        proxy_auth.insert(proxy.as_str().to_string(), credentials.clone());
+        Some(credentials)
+    }
+
+    /// Route the routes pick for `request`, `None` when there are none or it
+    /// has a proxy of its own.
+    fn route(&self, request: &Request) -> Option<Route> {
+        let routes = self.routes.as_ref()?;
+        if request.get_proxy().is_some() {
+            return None;
+        }
+        Some(routes.route(&request.get_uri()?))
+    }
+
+    async fn send_once(&self, request: &Request) -> result::Result<Response, Failed> {
+        let uri = request.get_uri().ok_or(Error::EmptyUri)?;
+        let proxy = request.get_proxy();
+        if self.no_local_dns {
+            check_no_local_dns(&uri, proxy.as_ref())?;
+        }
+        let key = pool_key(&uri, proxy.as_ref());
+        if let Some(stream) = self.take(&key) {
+            match self.send_on(stream, &key, request).await {
+                Ok(response) => return Ok(response),
+                Err(err) if !is_idempotent(&request.get_method()) => {
+                    return Err(Failed::written(err))
+                }
+                Err(_) => (),
+            }
+        }
+        let stream = MaybeHttpsStream::connect(&uri, proxy.as_ref()).await?;
+        self.send_on(stream, &key, request)
+            .await
+            .map_err(Failed::written)
+    }
+
+    async fn send_on(
+        &self,
+        mut stream: MaybeHttpsStream,
+        key: &str,
+        request: &Request,
+    ) -> Result<Response> {
+        let reusable = reusable(request);
+        let request = reusable.as_ref().unwrap_or(request);
+        stream.send_msg(&request.to_vec()).await?;
+        let decoder = ResponseDecoder::with_method(&request.get_method());
+        let response = stream.read_response(decoder).await?;
+        if keep_alive(request, &response) {
+            self.put(key, stream);
+        }
+        Ok(response)
+    }
+
+    fn take(&self, key: &str) -> Option<MaybeHttpsStream> {
+        let mut idle = self.inner.idle.lock().ok()?;
+        idle.get_mut(key).and_then(|streams| streams.pop())
+    }
+
+    fn put(&self, key: &str, stream: MaybeHttpsStream) {
+        if let Ok(mut idle) = self.inner.idle.lock() {
+            let streams = idle.entry(key.to_string()).or_insert_with(Vec::new);
+            if streams.len() < self.inner.max_idle_per_host {
+                streams.push(stream);
+            }
+        }
+    }
+}
+
+/// Error of one attempt at a request. `written` once the request went out on
+/// the connection, from then on it may have reached the origin.
+#[derive(Debug)]
+struct Failed {
+    err: Error,
+    written: bool,
+}
+
+impl Failed {
+    fn written(err: Error) -> Failed {
+        Failed { err, written: true }
+    }
+}
+
+/// Errors from connecting, handshaking or checking the request, before
+/// anything was written.
+impl From<Error> for Failed {
+    fn from(err: Error) -> Failed {
+        Failed {
+            err,
+            written: false,
+        }
+    }
+}
+
+fn timed_out() -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "request through proxy timed out",
+    ))
+}
+
+fn pool_key(uri: &Uri, proxy: Option<&Uri>) -> String {
+    let host_port = uri
+        .host_port()
+        .map_or(String::new(), |host_port| host_port.to_string());
+    match proxy {
+        Some(proxy) => format!("{}://{} via {}", uri.scheme(), host_port, proxy.as_str()),
+        None => format!("{}://{}", uri.scheme(), host_port),
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    !matches!(method, Method::POST | Method::CONNECT | Method::Custom(_))
+}
+
+fn has_token(value: Option<String>, token: &str) -> bool {
+    match value {
+        Some(value) => value
+            .split(',')
+            .any(|item| item.trim().eq_ignore_ascii_case(token)),
+        None => false,
+    }
+}
+
+/// Copy of `request` asking to keep the connection open in place of the
+/// `Connection: Close` every `Request::new` adds, `None` when it does not
+/// ask to close. The pool decides itself whether a connection is kept, see
+/// `max_idle_per_host`.
+fn reusable(request: &Request) -> Option<Request> {
+    if !has_token(request.get_headers().get("Connection"), "close") {
+        return None;
+    }
+    let mut request = request.clone();
+    request.header("Connection", "keep-alive");
+    Some(request)
+}
+
+/// A connection can be reused when neither side asked to close it and the
+/// response body was not delimited by closing the connection.
+fn keep_alive(request: &Request, response: &Response) -> bool {
+    let headers = response.headers();
+    if has_token(request.get_headers().get("Connection"), "close")
+        || has_token(headers.get("Connection"), "close")
+    {
+        return false;
+    }
+    if response.version() == "HTTP/1.0" && !has_token(headers.get("Connection"), "keep-alive") {
+        return false;
+    }
+    let code = response.status_code();
+    request.get_method() == Method::HEAD
+        || code.is(|c| c == 204 || c == 304)
+        || headers.get("Content-Length").is_some()
+        || match headers.get("Transfer-Encoding") {
+            Some(encoding) => is_chunked(&encoding),
+            None => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn pool_keep_alive() {
+        let uri = "http://api.ipify.org".parse().unwrap();
+        let mut request = Request::new(&uri, None);
+        let response =
+            Response::from_header(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert!(!keep_alive(&request, &response));
+
+        request.header("Connection", "keep-alive");
+        assert!(keep_alive(&request, &response));
+
+        let response = Response::from_header(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert!(!keep_alive(&request, &response));
+
+        let response =
+            Response::from_header(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert!(!keep_alive(&request, &response));
+    }
+
+    #[tokio::test]
+    async fn pool_reuses_connection() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .await
+                    .unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+
+        let pool = Pool::default();
+        let request = Request::new(&uri, None);
+        for _ in 0..2 {
+            let response = tokio::time::timeout(Duration::from_secs(5), pool.send(&request))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.body(), &b"ok"[..]);
+        }
+        assert_eq!(pool.idle(), 1);
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("connection: keep-alive"));
+        assert!(requests[1].contains("connection: keep-alive"));
+    }
+
+    #[test]
+    fn pool_key_proxy() {
+        let uri = "http://api.ipify.org".parse().unwrap();
+        let proxy = "socks5://127.0.0.1:5959".parse().unwrap();
+
+        assert_ne!(pool_key(&uri, None), pool_key(&uri, Some(&proxy)));
+    }
+
+    #[test]
+    fn pool_routes() {
+        let proxy: Uri = "http://127.0.0.1:3128".parse().unwrap();
+        let pool = Pool::default().routes(Routes::new().proxy(
+            crate::route::Match::new().host("*.ipify.org"),
+            proxy.clone(),
+        ));
+        let uri = "http://api.ipify.org/ip".parse().unwrap();
+        assert_eq!(
+            pool.route(&Request::new(&uri, None)),
+            Some(Route::Proxy(proxy))
+        );
+
+        let own = "socks5://127.0.0.1:5959".parse().unwrap();
+        assert!(pool.route(&Request::new(&uri, Some(&own))).is_none());
+        let uri = "http://example.com/".parse().unwrap();
+        assert_eq!(pool.route(&Request::new(&uri, None)), Some(Route::Direct));
+    }
+
+    #[tokio::test]
+    async fn pool_keeps_proxy_auth() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: Uri = format!("http://user:pass@{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut requests = Vec::new();
+            for _ in 0..3 {
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                let header = String::from_utf8(header).unwrap().to_ascii_lowercase();
+                let response: &[u8] = if header.contains("proxy-authorization: digest") {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                } else {
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                      Proxy-Authenticate: Digest realm=\"proxy\", nonce=\"abc\", qop=\"auth\"\r\n\
+                      Content-Length: 0\r\n\r\n"
+                };
+                socket.write_all(response).await.unwrap();
+                requests.push(header);
+            }
+            requests
+        });
+
+        let pool = Pool::default();
+        let uri: Uri = "http://example.com/".parse().unwrap();
+        let request = Request::new(&uri, Some(&proxy));
+        for _ in 0..2 {
+            let response = tokio::time::timeout(Duration::from_secs(5), pool.send(&request))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.body(), &b"ok"[..]);
+        }
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("proxy-authorization"));
+        assert!(requests[1].contains("nc=00000001"));
+        assert!(requests[2].contains("nc=00000002"));
+
+        let pool = Pool::default().preemptive_proxy_auth();
+        let mut request = request.clone();
+        let credentials = pool.proxy_credentials(&request).unwrap();
+        credentials
+            .before_send(&mut request, Target::Proxy)
+            .unwrap();
+        assert_eq!(
+            request.get_headers().get("Proxy-Authorization"),
+            Some("Basic dXNlcjpwYXNz".to_string())
+        );
+    }
+}
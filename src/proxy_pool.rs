@@ -0,0 +1,617 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    convert::TryInto,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tokio::time;
+use uri::Uri;
+
+use crate::error::Error;
+use crate::method::Method;
+use crate::pool::Pool;
+use crate::request::Request;
+
+const MAX_FAILURES: u32 = 3;
+const COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_ATTEMPTS: usize = 3;
+const CHECK_CONCURRENCY: usize = 32;
+
+/// How a `ProxyPool` picks among the proxies that are up, round-robin by
+/// default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    RoundRobin,
+    Random,
+    /// The proxy with the lowest measured latency, ones never measured first.
+    LeastLatency,
+    /// The same proxy for every request to a host while it stays up.
+    StickyPerHost,
+}
+
+#[derive(Debug)]
+struct Entry {
+    proxy: Uri,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+    latency: Option<Duration>,
+}
+
+impl Health {
+    fn is_up(&self, now: Instant) -> bool {
+        !matches!(self.down_until, Some(until) if until > now)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: Vec<Entry>,
+    next: AtomicUsize,
+    sticky: Mutex<HashMap<String, usize>>,
+}
+
+/// Set of proxies shared by clones, requests are spread over them by the
+/// strategy. A proxy failing `max_failures` times in a row is left out for
+/// `cooldown`, a success resets its count. `Pool::proxies` and `Route::Pool`
+/// send through it and fail over to another proxy on proxy errors.
+#[derive(Debug, Clone)]
+pub struct ProxyPool {
+    state: Arc<State>,
+    strategy: Strategy,
+    max_failures: u32,
+    cooldown: Duration,
+    max_attempts: usize,
+    timeout: Option<Duration>,
+    health_check: Option<(Uri, Duration)>,
+    check_concurrency: usize,
+}
+
+/// Pools are equal when they share their proxies and health, as clones do.
+impl PartialEq for ProxyPool {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+            && self.strategy == other.strategy
+            && self.max_failures == other.max_failures
+            && self.cooldown == other.cooldown
+            && self.max_attempts == other.max_attempts
+            && self.timeout == other.timeout
+            && self.health_check == other.health_check
+            && self.check_concurrency == other.check_concurrency
+    }
+}
+
+impl ProxyPool {
+    /// Pool of `proxies`, entries that are not valid URIs are skipped.
+    pub fn new<I, P>(proxies: I) -> ProxyPool
+    where
+        I: IntoIterator<Item = P>,
+        P: TryInto<Uri>,
+    {
+        let entries = proxies
+            .into_iter()
+            .filter_map(|proxy| proxy.try_into().ok())
+            .map(|proxy| Entry {
+                proxy,
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+        ProxyPool {
+            state: Arc::new(State {
+                entries,
+                ..State::default()
+            }),
+            strategy: Strategy::RoundRobin,
+            max_failures: MAX_FAILURES,
+            cooldown: COOLDOWN,
+            max_attempts: MAX_ATTEMPTS,
+            timeout: None,
+            health_check: None,
+            check_concurrency: CHECK_CONCURRENCY,
+        }
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> ProxyPool {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Failures in a row putting a proxy in cooldown, at least 1.
+    pub fn max_failures(mut self, failures: u32) -> ProxyPool {
+        self.max_failures = failures.max(1);
+        self
+    }
+
+    pub fn cooldown(mut self, cooldown: Duration) -> ProxyPool {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Proxies a request is tried through before its error is returned, at
+    /// least 1.
+    pub fn max_attempts(mut self, attempts: usize) -> ProxyPool {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Time allowed to a request through one proxy, a proxy taking longer
+    /// fails with a timed out `Error::IO`, which counts against it.
+    pub fn timeout(mut self, timeout: Duration) -> ProxyPool {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Probes every proxy with a HEAD request to `target` on `check`, a probe
+    /// taking longer than `timeout` counts as a failure.
+    pub fn health_check<T: TryInto<Uri>>(mut self, target: T, timeout: Duration) -> ProxyPool {
+        self.health_check = target.try_into().ok().map(|target| (target, timeout));
+        self
+    }
+
+    /// Probes `check` runs at once, at least 1.
+    pub fn check_concurrency(mut self, probes: usize) -> ProxyPool {
+        self.check_concurrency = probes.max(1);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.entries.is_empty()
+    }
+
+    /// Proxies not in cooldown.
+    pub fn available(&self) -> Vec<Uri> {
+        let now = Instant::now();
+        self.state
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.health.lock(), Ok(health) if health.is_up(now)))
+            .map(|entry| entry.proxy.clone())
+            .collect()
+    }
+
+    pub(crate) fn attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    pub(crate) fn attempt_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Proxy for a request to `uri`, `None` when all are in cooldown.
+    pub fn pick(&self, uri: &Uri) -> Option<Uri> {
+        self.pick_except(uri, &[])
+    }
+
+    pub(crate) fn pick_except(&self, uri: &Uri, tried: &[Uri]) -> Option<Uri> {
+        let now = Instant::now();
+        let candidates: Vec<(usize, Option<Duration>)> = self
+            .state
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !tried.contains(&entry.proxy))
+            .filter_map(|(idx, entry)| {
+                let health = entry.health.lock().ok()?;
+                if health.is_up(now) {
+                    Some((idx, health.latency))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let idx = match self.strategy {
+            Strategy::RoundRobin => self.next(&candidates),
+            Strategy::Random => candidates[random_index(candidates.len())].0,
+            Strategy::LeastLatency => candidates
+                .iter()
+                .min_by_key(|(_, latency)| latency.unwrap_or_default())
+                .map(|(idx, _)| *idx)?,
+            Strategy::StickyPerHost => {
+                let host = uri.host_header();
+                let mut sticky = self.state.sticky.lock().ok()?;
+                match sticky.get(&host) {
+                    Some(idx) if candidates.iter().any(|(up, _)| up == idx) => *idx,
+                    _ => {
+                        let idx = self.next(&candidates);
+                        sticky.insert(host, idx);
+                        idx
+                    }
+                }
+            }
+        };
+        Some(self.state.entries[idx].proxy.clone())
+    }
+
+    fn next(&self, candidates: &[(usize, Option<Duration>)]) -> usize {
+        let turn = self.state.next.fetch_add(1, Ordering::Relaxed);
+        candidates[turn % candidates.len()].0
+    }
+
+    /// Records a request through `proxy` answered after `latency`.
+    pub fn mark_ok(&self, proxy: &Uri, latency: Duration) {
+        self.update(proxy, |health| {
+            health.failures = 0;
+            health.down_until = None;
+            health.latency = Some(match health.latency {
+                Some(old) => (old * 3 + latency) / 4,
+                None => latency,
+            });
+        });
+    }
+
+    /// Records a proxy error through `proxy`.
+    pub fn mark_failed(&self, proxy: &Uri) {
+        let (max_failures, cooldown) = (self.max_failures, self.cooldown);
+        self.update(proxy, |health| {
+            health.failures = health.failures.saturating_add(1);
+            if health.failures >= max_failures {
+                health.down_until = Some(Instant::now() + cooldown);
+            }
+        });
+    }
+
+    fn update<F: FnOnce(&mut Health)>(&self, proxy: &Uri, update: F) {
+        let entry = self
+            .state
+            .entries
+            .iter()
+            .find(|entry| entry.proxy == *proxy);
+        if let Some(mut health) = entry.and_then(|entry| entry.health.lock().ok()) {
+            update(&mut health);
+        }
+    }
+
+    /// Probes every proxy once when a health check is set, marking each up or
+    /// failed, proxies in cooldown included. Up to `check_concurrency` probes
+    /// run at once.
+    pub async fn check(&self) {
+        let (target, timeout) = match &self.health_check {
+            Some(check) => check,
+            None => return,
+        };
+        let pool = Pool::new(self.check_concurrency, 0);
+        let probes = self.state.entries.iter().map(|entry| {
+            let pool = &pool;
+            async move {
+                let mut request = Request::new(target, Some(&entry.proxy));
+                request.method(Method::HEAD);
+                let start = Instant::now();
+                match time::timeout(*timeout, pool.send(&request)).await {
+                    Ok(Ok(_)) => self.mark_ok(&entry.proxy, start.elapsed()),
+                    _ => self.mark_failed(&entry.proxy),
+                }
+            }
+        });
+        Bounded::new(probes, self.check_concurrency).await;
+    }
+
+    /// Runs `check` every `interval`, never returns. Spawn it on a runtime to
+    /// keep the pool checked in the background.
+    pub async fn run_health_checks(&self, interval: Duration) {
+        loop {
+            self.check().await;
+            time::sleep(interval).await;
+        }
+    }
+}
+
+/// Errors raised by the proxy or the connection to it rather than by the
+/// origin, which answers with a response.
+pub(crate) fn is_proxy_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::IO(_)
+            | Error::EmptyResponse
+            | Error::TunnelFailed(_)
+            | Error::ProxyAuthRequired { .. }
+            | Error::Socks4(_)
+            | Error::Socks5(_)
+            | Error::Socks5Negotiation(_)
+            | Error::ProxyHop(..)
+    )
+}
+
+/// Runs the futures of an iterator to completion, at most `limit` at once.
+struct Bounded<I: Iterator> {
+    pending: I,
+    running: Vec<Pin<Box<I::Item>>>,
+    limit: usize,
+}
+
+impl<I> Bounded<I>
+where
+    I: Iterator,
+    I::Item: Future<Output = ()>,
+{
+    fn new(pending: I, limit: usize) -> Bounded<I> {
+        Bounded {
+            pending,
+            running: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl<I> Future for Bounded<I>
+where
+    I: Iterator + Unpin,
+    I::Item: Future<Output = ()>,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            while this.running.len() < this.limit {
+                match this.pending.next() {
+                    Some(future) => this.running.push(Box::pin(future)),
+                    None => break,
+                }
+            }
+            let running = this.running.len();
+            let mut idx = 0;
+            while idx < this.running.len() {
+                if this.running[idx].as_mut().poll(cx).is_ready() {
+                    drop(this.running.swap_remove(idx));
+                } else {
+                    idx += 1;
+                }
+            }
+            if this.running.is_empty() {
+                return Poll::Ready(());
+            }
+            if this.running.len() == running {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+fn random_index(len: usize) -> usize {
+    RandomState::new().build_hasher().finish() as usize % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    /// Answers `count` requests with `response`, returns their heads.
+    async fn serve(listener: TcpListener, response: &'static str, count: usize) -> Vec<String> {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut header = Vec::new();
+            while !header.ends_with(b"\r\n\r\n") {
+                header.push(socket.read_u8().await.unwrap());
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            requests.push(String::from_utf8(header).unwrap());
+        }
+        requests
+    }
+
+    /// Address nothing listens on.
+    async fn dead_proxy() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn proxy_pool_strategies() {
+        let target = uri("http://api.ipify.org/");
+        let other = uri("http://example.com/");
+        let proxies = ["http://127.0.0.1:3128", "http://127.0.0.1:3129", "bad uri"];
+        let pool = ProxyPool::new(proxies.iter().copied());
+        assert_eq!(pool.len(), 2);
+        let first = pool.pick(&target).unwrap();
+        let second = pool.pick(&target).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(pool.pick(&target), Some(first.clone()));
+
+        let pool = ProxyPool::new(proxies.iter().copied())
+            .strategy(Strategy::StickyPerHost)
+            .max_failures(1);
+        let sticky = pool.pick(&target).unwrap();
+        assert_ne!(pool.pick(&other).unwrap(), sticky);
+        assert_eq!(pool.pick(&target), Some(sticky.clone()));
+        pool.mark_failed(&sticky);
+        assert_ne!(pool.pick(&target), Some(sticky));
+
+        let pool = ProxyPool::new(proxies.iter().copied()).strategy(Strategy::LeastLatency);
+        pool.mark_ok(&first, Duration::from_millis(200));
+        pool.mark_ok(&second, Duration::from_millis(20));
+        assert_eq!(pool.pick(&target), Some(second.clone()));
+
+        let pool = ProxyPool::new(proxies.iter().copied()).strategy(Strategy::Random);
+        assert!(pool.pick(&target).is_some());
+    }
+
+    #[test]
+    fn proxy_pool_cooldown() {
+        let target = uri("http://api.ipify.org/");
+        let proxy = uri("http://127.0.0.1:3128");
+        let pool = ProxyPool::new(vec![proxy.clone()]).max_failures(2);
+        pool.mark_failed(&proxy);
+        assert_eq!(pool.pick(&target), Some(proxy.clone()));
+        pool.mark_failed(&proxy);
+        assert_eq!(pool.pick(&target), None);
+        assert!(pool.available().is_empty());
+
+        pool.mark_ok(&proxy, Duration::from_millis(10));
+        assert_eq!(pool.available(), vec![proxy.clone()]);
+
+        let pool = pool.cooldown(Duration::from_secs(0));
+        pool.mark_failed(&proxy);
+        pool.mark_failed(&proxy);
+        assert_eq!(pool.pick(&target), Some(proxy));
+    }
+
+    #[tokio::test]
+    async fn proxy_pool_failover() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let live = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(serve(
+            listener,
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n",
+            2,
+        ));
+        let dead = dead_proxy().await;
+        let proxies = ProxyPool::new(vec![dead.as_str(), live.as_str()]).max_failures(1);
+        let pool = Pool::default().proxies(proxies.clone());
+        let target = uri("http://origin.invalid/path");
+
+        let response = pool.send(&Request::new(&target, None)).await?;
+        assert_eq!(u16::from(response.status_code()), 502);
+        assert_eq!(proxies.available(), vec![uri(&live)]);
+        let response = pool.send(&Request::new(&target, None)).await?;
+        assert_eq!(u16::from(response.status_code()), 502);
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("GET http://origin.invalid:80/path HTTP/1.1"));
+        assert_eq!(requests.len(), 2);
+
+        let dead = ProxyPool::new(vec![dead_proxy().await])
+            .max_failures(1)
+            .max_attempts(1);
+        let pool = Pool::default().proxies(dead);
+        assert!(pool.send(&Request::new(&target, None)).await.is_err());
+        assert_eq!(
+            pool.send(&Request::new(&target, None)).await.unwrap_err(),
+            Error::NoProxyAvailable
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_pool_failover_sent() -> Result<()> {
+        use crate::route::{Route, Routes};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let live = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(serve(
+            listener,
+            "HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n",
+            1,
+        ));
+        let dead = dead_proxy().await;
+        let proxies = ProxyPool::new(vec![dead.as_str(), live.as_str()]).max_failures(1);
+        let pool = Pool::default().routes(Routes::new().otherwise(Route::Pool(proxies.clone())));
+        let target = uri("http://origin.invalid/path");
+        let mut request = Request::new(&target, None);
+        request.method(Method::POST);
+
+        let response = pool.send(&request).await?;
+        assert_eq!(u16::from(response.status_code()), 201);
+        assert_eq!(proxies.available(), vec![uri(&live)]);
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("POST http://origin.invalid:80/path HTTP/1.1"));
+
+        // The proxy takes the request and drops the connection.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let hangup = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut header = Vec::new();
+            while !header.ends_with(b"\r\n\r\n") {
+                header.push(socket.read_u8().await.unwrap());
+            }
+        });
+        let proxies = ProxyPool::new(vec![hangup.as_str(), live.as_str()]).max_failures(1);
+        let pool = Pool::default().proxies(proxies.clone());
+        assert!(pool.send(&request).await.is_err());
+        assert_eq!(proxies.available(), vec![uri(&live)]);
+        server.await.unwrap();
+        Ok(())
+    }
+
+    /// Proxy taking connections and never answering, kept open until the
+    /// returned task is aborted.
+    async fn silent_proxy() -> (String, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            loop {
+                sockets.push(listener.accept().await.unwrap());
+            }
+        });
+        (proxy, server)
+    }
+
+    #[tokio::test]
+    async fn proxy_pool_timeout() {
+        let (silent, server) = silent_proxy().await;
+        let proxies = ProxyPool::new(vec![silent.as_str()])
+            .max_failures(1)
+            .timeout(Duration::from_millis(100));
+        let pool = Pool::default().proxies(proxies.clone());
+        let target = uri("http://origin.invalid/");
+        let err = pool.send(&Request::new(&target, None)).await.unwrap_err();
+        assert!(matches!(err, Error::IO(err) if err.kind() == std::io::ErrorKind::TimedOut));
+        assert!(proxies.available().is_empty());
+
+        let mut silent = Vec::new();
+        for _ in 0..4 {
+            silent.push(silent_proxy().await);
+        }
+        let proxies = ProxyPool::new(silent.iter().map(|(proxy, _)| proxy.as_str()))
+            .max_failures(1)
+            .health_check("http://origin.invalid/", Duration::from_millis(200))
+            .check_concurrency(4);
+        let start = Instant::now();
+        proxies.check().await;
+        assert!(start.elapsed() < Duration::from_millis(600));
+        assert!(proxies.available().is_empty());
+
+        server.abort();
+        for (_, server) in silent {
+            server.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_pool_health_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve(
+            listener,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+            1,
+        ));
+        let dead = dead_proxy().await;
+        let proxies = ProxyPool::new(vec![dead.as_str(), live.as_str()])
+            .max_failures(1)
+            .health_check("http://origin.invalid/", Duration::from_secs(5));
+        proxies.check().await;
+
+        assert_eq!(proxies.available(), vec![uri(&live)]);
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("HEAD http://origin.invalid:80/ HTTP/1.1"));
+    }
+}
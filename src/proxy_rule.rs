@@ -0,0 +1,299 @@
+use std::{convert::TryInto, env, net::IpAddr};
+
+use uri::Uri;
+
+use crate::error::{Error, Result};
+use crate::stream::{target_ip, target_port};
+
+/// Hosts reached without a proxy, parsed from a `NO_PROXY` list: comma or
+/// space separated host names matching themselves and their subdomains, IP
+/// addresses, CIDR ranges, an optional `:port` and `*` for every host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NoProxy {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    All,
+    Domain(String, Option<u16>),
+    Net(IpAddr, u8, Option<u16>),
+}
+
+impl NoProxy {
+    /// Parses `list`, entries that are not valid are skipped.
+    pub fn parse(list: &str) -> NoProxy {
+        let entries = list
+            .split([',', ' ', '\t'].as_ref())
+            .filter_map(|entry| parse_entry(&entry.trim().to_ascii_lowercase()))
+            .collect();
+        NoProxy { entries }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let port = target_port(uri).ok();
+        let ip = target_ip(uri);
+        let host = uri.host_str().trim_end_matches('.').to_ascii_lowercase();
+        self.entries.iter().any(|entry| match entry {
+            Entry::All => true,
+            Entry::Domain(domain, entry_port) => {
+                port_matches(*entry_port, port)
+                    && (host == *domain
+                        || (ip.is_none() && host.ends_with(&format!(".{}", domain))))
+            }
+            Entry::Net(net, prefix, entry_port) => {
+                port_matches(*entry_port, port)
+                    && matches!(ip, Some(ip) if in_network(ip, *net, *prefix))
+            }
+        })
+    }
+}
+
+fn port_matches(entry: Option<u16>, port: Option<u16>) -> bool {
+    entry.is_none() || entry == port
+}
+
+fn parse_entry(entry: &str) -> Option<Entry> {
+    if entry.is_empty() {
+        return None;
+    }
+    if entry == "*" {
+        return Some(Entry::All);
+    }
+    if let Some((addr, prefix)) = entry.split_once('/') {
+        let ip: IpAddr = strip_brackets(addr).parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        if prefix > max_prefix(ip) {
+            return None;
+        }
+        return Some(Entry::Net(ip, prefix, None));
+    }
+    if let Ok(ip) = strip_brackets(entry).parse::<IpAddr>() {
+        return Some(Entry::Net(ip, max_prefix(ip), None));
+    }
+    let (host, port) = match entry.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.parse::<u16>().ok()?)),
+        None => (entry, None),
+    };
+    if let Ok(ip) = strip_brackets(host).parse::<IpAddr>() {
+        return Some(Entry::Net(ip, max_prefix(ip), port));
+    }
+    let host = host.trim_start_matches("*.").trim_start_matches('.');
+    let host = host.trim_end_matches('.');
+    if host.is_empty() {
+        return None;
+    }
+    Some(Entry::Domain(host.to_string(), port))
+}
+
+fn strip_brackets(addr: &str) -> &str {
+    addr.trim_start_matches('[').trim_end_matches(']')
+}
+
+pub(crate) fn max_prefix(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+pub(crate) fn in_network(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Picks the proxy for a target by its scheme, as curl does with
+/// `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy`. A scheme without
+/// its own proxy falls back to the `all` one, hosts in `no_proxy` go direct.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProxyRule {
+    http: Option<Uri>,
+    https: Option<Uri>,
+    all: Option<Uri>,
+    no_proxy: NoProxy,
+}
+
+impl ProxyRule {
+    pub fn new() -> ProxyRule {
+        ProxyRule::default()
+    }
+
+    /// Rule read from the environment. As curl does, empty variables count as
+    /// unset, lowercase names are looked up before uppercase ones, and `HTTP_PROXY` is ignored since CGI servers set it
+    /// from the `Proxy` request header. A proxy without a scheme is `http`, a
+    /// value that does not parse fails with `Error::ProxyEnv`.
+    pub fn from_env() -> Result<ProxyRule> {
+        ProxyRule::from_lookup(|name| env::var(name).ok())
+    }
+
+    fn from_lookup<F>(lookup: F) -> Result<ProxyRule>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                lookup(name)
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+                    .map(|value| (name.to_string(), value))
+            })
+        };
+        let proxy = |names: &[&str]| match var(names) {
+            Some((name, value)) => {
+                let value = if value.contains("://") {
+                    value
+                } else {
+                    format!("http://{}", value)
+                };
+                match value.parse() {
+                    Ok(proxy) => Ok(Some(proxy)),
+                    Err(_) => Err(Error::ProxyEnv(name)),
+                }
+            }
+            None => Ok(None),
+        };
+        Ok(ProxyRule {
+            http: proxy(&["http_proxy"])?,
+            https: proxy(&["https_proxy", "HTTPS_PROXY"])?,
+            all: proxy(&["all_proxy", "ALL_PROXY"])?,
+            no_proxy: NoProxy::parse(&var(&["no_proxy", "NO_PROXY"]).unwrap_or_default().1),
+        })
+    }
+
+    pub fn http<P: TryInto<Uri>>(mut self, proxy: P) -> ProxyRule {
+        self.http = proxy.try_into().ok();
+        self
+    }
+
+    pub fn https<P: TryInto<Uri>>(mut self, proxy: P) -> ProxyRule {
+        self.https = proxy.try_into().ok();
+        self
+    }
+
+    pub fn all<P: TryInto<Uri>>(mut self, proxy: P) -> ProxyRule {
+        self.all = proxy.try_into().ok();
+        self
+    }
+
+    pub fn no_proxy(mut self, list: &str) -> ProxyRule {
+        self.no_proxy = NoProxy::parse(list);
+        self
+    }
+
+    /// Proxy for `uri`, `None` to go direct.
+    pub fn select(&self, uri: &Uri) -> Option<Uri> {
+        if self.no_proxy.matches(uri) {
+            return None;
+        }
+        let proxy = match uri.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        };
+        proxy.or(self.all.as_ref()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    fn no_proxy_matches() {
+        let no_proxy = NoProxy::parse(
+            "localhost, .internal.corp,example.com:8080 10.0.0.0/8,[::1],192.168.1.5:22",
+        );
+        assert!(no_proxy.matches(&uri("http://localhost/")));
+        assert!(no_proxy.matches(&uri("http://api.internal.corp/")));
+        assert!(no_proxy.matches(&uri("http://internal.corp/")));
+        assert!(!no_proxy.matches(&uri("http://xinternal.corp/")));
+        assert!(no_proxy.matches(&uri("http://www.example.com:8080/")));
+        assert!(!no_proxy.matches(&uri("http://example.com/")));
+        assert!(no_proxy.matches(&uri("http://10.20.30.40/")));
+        assert!(!no_proxy.matches(&uri("http://11.0.0.1/")));
+        assert!(no_proxy.matches(&uri("http://[::1]:8080/")));
+        assert!(!no_proxy.matches(&uri("http://192.168.1.5/")));
+
+        assert!(NoProxy::parse("*").matches(&uri("https://api.ipify.org/")));
+        assert!(NoProxy::parse("10.0.0.0/33, :x").is_empty());
+    }
+
+    #[test]
+    fn proxy_rule_select() {
+        let rule = ProxyRule::new()
+            .http("http://127.0.0.1:3128")
+            .all("socks5h://127.0.0.1:1080")
+            .no_proxy("localhost");
+        assert_eq!(
+            rule.select(&uri("http://api.ipify.org/")),
+            Some(uri("http://127.0.0.1:3128"))
+        );
+        assert_eq!(
+            rule.select(&uri("https://api.ipify.org/")),
+            Some(uri("socks5h://127.0.0.1:1080"))
+        );
+        assert_eq!(rule.select(&uri("http://localhost:8080/")), None);
+    }
+
+    #[test]
+    fn proxy_rule_env() {
+        let rule = ProxyRule::from_lookup(|name| {
+            match name {
+                "http_proxy" => Some("proxy.corp:3128"),
+                "HTTP_PROXY" => Some("http://cgi.example:80"),
+                "https_proxy" => Some(""),
+                "HTTPS_PROXY" => Some("http://secure.corp:3128"),
+                "ALL_PROXY" => Some("socks5h://all.corp:1080"),
+                "no_proxy" => Some("internal.corp"),
+                "NO_PROXY" => Some("*"),
+                _ => None,
+            }
+            .map(|value| value.to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            rule.select(&uri("http://api.ipify.org/")),
+            Some(uri("http://proxy.corp:3128"))
+        );
+        assert_eq!(
+            rule.select(&uri("https://api.ipify.org/")),
+            Some(uri("http://secure.corp:3128"))
+        );
+        assert_eq!(rule.select(&uri("http://a.internal.corp/")), None);
+
+        let rule = ProxyRule::from_lookup(|name| {
+            match name {
+                "ALL_PROXY" => Some("socks5h://all.corp:1080"),
+                "no_proxy" => Some(" "),
+                "NO_PROXY" => Some("*"),
+                _ => None,
+            }
+            .map(|value| value.to_string())
+        })
+        .unwrap();
+        assert_eq!(rule.select(&uri("http://api.ipify.org/")), None);
+
+        let rule = ProxyRule::from_lookup(|name| match name {
+            "https_proxy" => Some("proxy.corp:port".to_string()),
+            _ => None,
+        });
+        assert_eq!(rule, Err(Error::ProxyEnv("https_proxy".to_string())));
+    }
+}
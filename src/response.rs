@@ -2,6 +2,7 @@ use std::{io::Write, str};
 
 use bytes::Bytes;
 
+use crate::charset;
 use crate::error::{Error, Result};
 use crate::headers::Headers;
 use crate::status::{Status, StatusCode};
@@ -11,6 +12,9 @@ pub struct Response {
     pub status: Status,
     pub headers: Headers,
     pub body: Bytes,
+    /// `Content-Encoding` of a body that has been decoded, see
+    /// `content_encoding()`.
+    pub content_encoding: Option<String>,
 }
 
 impl Response {
@@ -25,6 +29,7 @@ impl Response {
             status,
             headers,
             body,
+            content_encoding: None,
         })
     }
 
@@ -60,6 +65,15 @@ impl Response {
         &self.headers
     }
 
+    /// `Content-Encoding` the body was sent with, also after it has been
+    /// decoded.
+    pub fn content_encoding(&self) -> Option<String> {
+        match &self.content_encoding {
+            Some(encoding) => Some(encoding.clone()),
+            None => self.headers.get("Content-Encoding"),
+        }
+    }
+
     pub fn content_len(&self) -> Result<usize> {
         match self.headers().get("Content-Length") {
             Some(p) => Ok(p.parse()?),
@@ -71,8 +85,22 @@ impl Response {
         self.body.clone()
     }
 
+    /// Body decoded with the charset found by `text_with_charset`, UTF-8 when
+    /// there is none.
     pub fn text(&self) -> Result<String> {
-        Ok(String::from_utf8_lossy(&self.body).to_string())
+        self.text_with_charset("utf-8").map(|(text, _)| text)
+    }
+
+    /// Body decoded with the `Content-Type` charset, a byte order mark, the
+    /// `<meta>` charset of a `text/html` body or else `default`, together
+    /// with the name of the encoding used.
+    pub fn text_with_charset(&self, default: &str) -> Result<(String, &'static str)> {
+        let content_type = self.headers.get("Content-Type");
+        Ok(charset::decode(
+            &self.body,
+            content_type.as_deref(),
+            default,
+        ))
     }
 }
 
@@ -179,6 +207,21 @@ mod tests {
         assert_eq!(res.content_len(), Ok(100));
     }
 
+    #[test]
+    fn res_text_with_charset() {
+        let mut res = Response::from_header(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=koi8-r\r\n\r\n",
+        )
+        .unwrap();
+        res.body = Bytes::from_static(&[0xf0, 0xd2, 0xc9, 0xd7, 0xc5, 0xd4]);
+
+        assert_eq!(res.text().unwrap(), "Привет");
+        assert_eq!(
+            res.text_with_charset("utf-8").unwrap(),
+            ("Привет".to_string(), "KOI8-R")
+        );
+    }
+
     #[test]
     fn res_body() {
         let mut writer = Vec::new();
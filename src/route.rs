@@ -0,0 +1,284 @@
+use std::{fmt, net::IpAddr, sync::Arc};
+
+use uri::Uri;
+
+use crate::proxy_pool::ProxyPool;
+use crate::proxy_rule::{in_network, max_prefix};
+use crate::stream::{target_ip, target_port};
+
+/// Where a request goes: straight to its target, through a proxy or through
+/// a proxy picked from a pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    Direct,
+    Proxy(Uri),
+    Pool(ProxyPool),
+}
+
+impl Route {
+    /// Proxy of the route, for `Pool` the one the pool picks for `uri`.
+    pub fn proxy(&self, uri: &Uri) -> Option<Uri> {
+        match self {
+            Route::Direct => None,
+            Route::Proxy(proxy) => Some(proxy.clone()),
+            Route::Pool(proxies) => proxies.pick(uri),
+        }
+    }
+}
+
+/// Conditions on the target of a request, all of those set must hold. A
+/// `Match` without any matches every target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Match {
+    scheme: Option<String>,
+    host: Option<String>,
+    net: Option<(IpAddr, u8)>,
+    port: Option<u16>,
+}
+
+impl Match {
+    pub fn new() -> Match {
+        Match::default()
+    }
+
+    pub fn scheme(mut self, scheme: &str) -> Match {
+        self.scheme = Some(scheme.to_ascii_lowercase());
+        self
+    }
+
+    /// Host name glob, `*` standing for any run of characters and `?` for
+    /// one, e.g. `*.corp.example`. Case is ignored.
+    pub fn host(mut self, glob: &str) -> Match {
+        self.host = Some(glob.to_ascii_lowercase());
+        self
+    }
+
+    /// Targets given by an IP address within `ip`/`prefix`, a prefix longer
+    /// than the address is cut to its length.
+    pub fn cidr(mut self, ip: IpAddr, prefix: u8) -> Match {
+        self.net = Some((ip, prefix.min(max_prefix(ip))));
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Match {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn matches(&self, uri: &Uri) -> bool {
+        if let Some(scheme) = &self.scheme {
+            if !uri.scheme().eq_ignore_ascii_case(scheme) {
+                return false;
+            }
+        }
+        if let Some(glob) = &self.host {
+            let host = uri
+                .host_str()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_ascii_lowercase();
+            if !glob_matches(glob.as_bytes(), host.as_bytes()) {
+                return false;
+            }
+        }
+        if let Some((net, prefix)) = self.net {
+            if !matches!(target_ip(uri), Some(ip) if in_network(ip, net, prefix)) {
+                return false;
+            }
+        }
+        match self.port {
+            Some(port) => target_port(uri).ok() == Some(port),
+            None => true,
+        }
+    }
+}
+
+/// Wildcard match with `*` for any run of bytes and `?` for one byte. On a
+/// mismatch the last `*` takes one more byte and matching resumes after it,
+/// so no pattern makes it recurse or backtrack further.
+fn glob_matches(glob: &[u8], text: &[u8]) -> bool {
+    let (mut g, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    star = Some((star_g, star_t + 1));
+                    g = star_g + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+type Select = Arc<dyn Fn(&Uri) -> Option<Route> + Send + Sync>;
+
+#[derive(Clone)]
+enum Rule {
+    Match(Box<Match>, Box<Route>),
+    Custom(Select),
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Rule::Match(when, route), Rule::Match(other_when, other_route)) => {
+                when == other_when && route == other_route
+            }
+            (Rule::Custom(select), Rule::Custom(other_select)) => Arc::ptr_eq(select, other_select),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Match(when, route) => f.debug_tuple("Match").field(when).field(route).finish(),
+            Rule::Custom(_) => f.debug_tuple("Custom").finish(),
+        }
+    }
+}
+
+/// Routing table picking the route of every request from its target. Rules
+/// are tried in the order they were added, the first one that applies wins
+/// and `otherwise`, direct by default, is taken when none does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routes {
+    rules: Vec<Rule>,
+    otherwise: Route,
+}
+
+impl Routes {
+    pub fn new() -> Routes {
+        Routes {
+            rules: Vec::new(),
+            otherwise: Route::Direct,
+        }
+    }
+
+    pub fn add(mut self, when: Match, route: Route) -> Routes {
+        self.rules
+            .push(Rule::Match(Box::new(when), Box::new(route)));
+        self
+    }
+
+    pub fn direct(self, when: Match) -> Routes {
+        self.add(when, Route::Direct)
+    }
+
+    pub fn proxy(self, when: Match, proxy: Uri) -> Routes {
+        self.add(when, Route::Proxy(proxy))
+    }
+
+    /// Rule deciding in code, `None` leaves the target to the next rules.
+    pub fn custom<F>(mut self, select: F) -> Routes
+    where
+        F: Fn(&Uri) -> Option<Route> + Send + Sync + 'static,
+    {
+        self.rules.push(Rule::Custom(Arc::new(select)));
+        self
+    }
+
+    pub fn otherwise(mut self, route: Route) -> Routes {
+        self.otherwise = route;
+        self
+    }
+
+    pub fn route(&self, uri: &Uri) -> Route {
+        self.rules
+            .iter()
+            .find_map(|rule| match rule {
+                Rule::Match(when, route) if when.matches(uri) => Some(Route::clone(route)),
+                Rule::Match(..) => None,
+                Rule::Custom(select) => select(uri),
+            })
+            .unwrap_or_else(|| self.otherwise.clone())
+    }
+
+    /// Proxy for `uri`, `None` to go direct.
+    pub fn select(&self, uri: &Uri) -> Option<Uri> {
+        self.route(uri).proxy(uri)
+    }
+}
+
+impl Default for Routes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Uri {
+        uri.parse().unwrap()
+    }
+
+    #[test]
+    fn route_match() {
+        let when = Match::new()
+            .scheme("HTTPS")
+            .host("*.corp.ex?mple")
+            .port(443);
+        assert!(when.matches(&uri("https://git.corp.example/")));
+        assert!(!when.matches(&uri("https://corp.example/")));
+        assert!(!when.matches(&uri("http://git.corp.example:443/")));
+        assert!(!when.matches(&uri("https://git.corp.example:8443/")));
+
+        let when = Match::new().cidr("10.0.0.0".parse().unwrap(), 8);
+        assert!(when.matches(&uri("http://10.1.2.3/")));
+        assert!(!when.matches(&uri("http://11.1.2.3/")));
+        assert!(!when.matches(&uri("http://ten.example/")));
+        assert!(Match::new().matches(&uri("http://api.ipify.org/")));
+    }
+
+    #[test]
+    fn route_glob() {
+        assert!(glob_matches(b"*", b""));
+        assert!(glob_matches(b"a*b*c", b"axxbyyc"));
+        assert!(glob_matches(b"*.corp", b"a.b.corp"));
+        assert!(glob_matches(b"a?c**", b"abc"));
+        assert!(!glob_matches(b"a*b", b"acbc"));
+        assert!(!glob_matches(b"?", b""));
+
+        let glob = "*a".repeat(50);
+        let text = "a".repeat(10_000) + "b";
+        assert!(!glob_matches(glob.as_bytes(), text.as_bytes()));
+    }
+
+    #[test]
+    fn routes_select() {
+        let corp = uri("http://proxy.corp.example:3128");
+        let socks = uri("socks5h://127.0.0.1:1080");
+        let routes = Routes::new()
+            .direct(Match::new().host("localhost"))
+            .custom(|uri| match uri.host_str() {
+                "blocked.example" => Some(Route::Direct),
+                _ => None,
+            })
+            .proxy(Match::new().host("*.corp.example"), corp.clone())
+            .otherwise(Route::Proxy(socks.clone()));
+
+        assert_eq!(routes.select(&uri("http://localhost:8080/")), None);
+        assert_eq!(routes.select(&uri("http://blocked.example/")), None);
+        assert_eq!(
+            routes.select(&uri("https://wiki.corp.example/")),
+            Some(corp)
+        );
+        assert_eq!(routes.select(&uri("https://api.ipify.org/")), Some(socks));
+        assert_eq!(routes.clone(), routes);
+    }
+}
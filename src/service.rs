@@ -0,0 +1,146 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::sync::OwnedSemaphorePermit;
+use tokio_util::sync::PollSemaphore;
+use tower_service::Service;
+
+use crate::error::{Error, Result};
+use crate::pool::Pool;
+use crate::request::Request;
+use crate::response::Response;
+
+/// Cloneable `tower::Service` handle over a shared `Pool`. `poll_ready`
+/// reserves one of the pool's connection slots, which is held until the
+/// response has been read.
+pub struct ClientService {
+    pool: Pool,
+    semaphore: PollSemaphore,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ClientService {
+    pub fn new() -> ClientService {
+        ClientService::with_pool(Pool::default())
+    }
+
+    pub fn with_pool(pool: Pool) -> ClientService {
+        ClientService {
+            semaphore: PollSemaphore::new(pool.semaphore()),
+            pool,
+            permit: None,
+        }
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+}
+
+impl Default for ClientService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for ClientService {
+    fn clone(&self) -> Self {
+        ClientService::with_pool(self.pool.clone())
+    }
+}
+
+impl fmt::Debug for ClientService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientService")
+            .field("pool", &self.pool)
+            .field("ready", &self.permit.is_some())
+            .finish()
+    }
+}
+
+impl Service<Request> for ClientService {
+    type Response = Response;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.permit.is_none() {
+            match self.semaphore.poll_acquire(cx) {
+                Poll::Ready(Some(permit)) => self.permit = Some(permit),
+                Poll::Ready(None) => return Poll::Ready(Err(Error::PoolClosed)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let permit = self
+            .permit
+            .take()
+            .expect("poll_ready must be called before call");
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let response = pool.send(&request).await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+    use uri::Uri;
+
+    #[tokio::test]
+    async fn service_reuses_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            for _ in 0..2 {
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+        let service = ClientService::new();
+        for _ in 0..2 {
+            let mut request = Request::new(&uri, None);
+            request.header("Connection", "keep-alive");
+            let response =
+                tokio::time::timeout(Duration::from_secs(5), service.clone().oneshot(request))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(response.text().unwrap(), "hello");
+        }
+        assert_eq!(service.pool().idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn service_backpressure() {
+        let mut service = ClientService::with_pool(Pool::new(1, 1));
+        let mut other = service.clone();
+        service.ready().await.unwrap();
+
+        let ready = tokio::time::timeout(Duration::from_millis(50), other.ready()).await;
+        assert!(ready.is_err());
+    }
+}
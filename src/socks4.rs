@@ -0,0 +1,227 @@
+use std::{fmt, net::SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use uri::Uri;
+
+use crate::error::{Error, Result};
+use crate::stream::target_port;
+
+const VERSION: u8 = 4;
+const CONNECT: u8 = 1;
+
+/// Reply code of a SOCKS4 server refusing a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reply {
+    /// 91, rejected or failed.
+    Rejected,
+    /// 92, the server cannot reach identd on the client.
+    IdentdUnreachable,
+    /// 93, identd reports another user than the user ID.
+    IdentdMismatch,
+    Unknown(u8),
+}
+
+impl Reply {
+    fn from_code(code: u8) -> Reply {
+        match code {
+            91 => Reply::Rejected,
+            92 => Reply::IdentdUnreachable,
+            93 => Reply::IdentdMismatch,
+            code => Reply::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Rejected => f.write_str("request rejected or failed"),
+            Reply::IdentdUnreachable => f.write_str("identd unreachable"),
+            Reply::IdentdMismatch => f.write_str("identd user ID mismatch"),
+            Reply::Unknown(code) => write!(f, "unknown reply {}", code),
+        }
+    }
+}
+
+/// Opens a connection to `target` through the SOCKS4 `proxy`. A `socks4a`
+/// proxy resolves the host name itself, `socks4` gets the first IPv4 address
+/// the name resolves to here. The user name of the proxy URI is sent as user ID.
+pub async fn connect(proxy: &Uri, target: &Uri) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy.socket_addr()?).await?;
+    handshake(&mut stream, proxy, target).await?;
+    Ok(stream)
+}
+
+/// Asks the SOCKS4 `proxy` at the other end of `stream` to connect to
+/// `target`, as `connect` does.
+pub async fn handshake<S>(stream: &mut S, proxy: &Uri, target: &Uri) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut msg = vec![VERSION, CONNECT];
+    let host = match proxy.scheme() {
+        "socks4a" => {
+            msg.extend_from_slice(&target_port(target)?.to_be_bytes());
+            msg.extend_from_slice(&[0, 0, 0, 1]);
+            Some(target.host_str().to_string())
+        }
+        _ => {
+            let host = target
+                .host_str()
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            let port = target_port(target)?;
+            match lookup_host((host, port)).await?.find(SocketAddr::is_ipv4) {
+                Some(SocketAddr::V4(addr)) => {
+                    msg.extend_from_slice(&addr.port().to_be_bytes());
+                    msg.extend_from_slice(&addr.ip().octets());
+                    None
+                }
+                _ => {
+                    let host_port = target.host_port().map(|host_port| host_port.to_string());
+                    return Err(Error::Socks4Address(host_port.unwrap_or_default()));
+                }
+            }
+        }
+    };
+    msg.extend_from_slice(user_id(proxy).as_bytes());
+    msg.push(0);
+    if let Some(host) = host {
+        msg.extend_from_slice(host.as_bytes());
+        msg.push(0);
+    }
+
+    stream.write_all(&msg).await?;
+    stream.flush().await?;
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await?;
+    match reply {
+        [0, 90, ..] => Ok(()),
+        [0, code, ..] => Err(Error::Socks4(Reply::from_code(code))),
+        _ => Err(Error::Socks4(Reply::Unknown(reply[1]))),
+    }
+}
+
+/// User name in the user info of `proxy`, empty without one.
+fn user_id(proxy: &Uri) -> String {
+    let rest = proxy
+        .as_str()
+        .split_once("://")
+        .map_or("", |(_, rest)| rest);
+    let authority = rest
+        .split(['/', '?', '#'].as_ref())
+        .next()
+        .unwrap_or_default();
+    match authority.rfind('@') {
+        Some(idx) => authority[..idx]
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Reads one SOCKS4 request and answers it with `code`, returns the
+    /// request up to the last NUL.
+    async fn serve(listener: TcpListener, code: u8) -> Vec<u8> {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![0u8; 8];
+        socket.read_exact(&mut request).await.unwrap();
+        // 0.0.0.x, x not 0, announces a host name after the user ID.
+        let fields = if request[4..7] == [0, 0, 0] && request[7] != 0 {
+            2
+        } else {
+            1
+        };
+        for _ in 0..fields {
+            loop {
+                let byte = socket.read_u8().await.unwrap();
+                request.push(byte);
+                if byte == 0 {
+                    break;
+                }
+            }
+        }
+        socket
+            .write_all(&[0, code, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        request
+    }
+
+    #[tokio::test]
+    async fn socks4_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 90));
+        let proxy: Uri = format!("socks4://bob:x@{}", addr).parse().unwrap();
+        let target: Uri = "http://127.0.0.1:8080/".parse().unwrap();
+        connect(&proxy, &target).await.unwrap();
+
+        assert_eq!(
+            server.await.unwrap(),
+            b"\x04\x01\x1f\x90\x7f\x00\x00\x01bob\x00".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn socks4a_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 90));
+        let proxy: Uri = format!("socks4a://{}", addr).parse().unwrap();
+        let target: Uri = "http://unresolved.invalid:8080/".parse().unwrap();
+        connect(&proxy, &target).await.unwrap();
+
+        assert_eq!(
+            server.await.unwrap(),
+            b"\x04\x01\x1f\x90\x00\x00\x00\x01\x00unresolved.invalid\x00".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn socks4_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 92));
+        let proxy: Uri = format!("socks4a://{}", addr).parse().unwrap();
+        let target: Uri = "http://unresolved.invalid:8080/".parse().unwrap();
+
+        assert_eq!(
+            connect(&proxy, &target).await.unwrap_err(),
+            Error::Socks4(Reply::IdentdUnreachable)
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks4_resolve_ipv4() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, 90));
+        let proxy: Uri = format!("socks4://{}", addr).parse().unwrap();
+        let target: Uri = "http://localhost:8080/".parse().unwrap();
+        connect(&proxy, &target).await.unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            b"\x04\x01\x1f\x90\x7f\x00\x00\x01\x00".to_vec()
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: Uri = format!("socks4://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let target: Uri = "http://[::1]:8080/".parse().unwrap();
+        assert!(matches!(
+            connect(&proxy, &target).await,
+            Err(Error::Socks4Address(_))
+        ));
+    }
+}
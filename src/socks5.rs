@@ -0,0 +1,383 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use uri::Uri;
+
+use crate::auth::user_info;
+use crate::error::{Error, Result};
+use crate::stream::{target_ip, target_port};
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const CONNECT: u8 = 1;
+
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Method negotiation or username/password authentication failing before
+/// the CONNECT request is sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Negotiation {
+    /// The proxy answered with another protocol version.
+    Version(u8),
+    /// 0xFF, none of the offered methods is acceptable to the proxy.
+    NoAcceptableMethods,
+    /// The proxy picked a method that was not offered.
+    UnofferedMethod(u8),
+    /// The proxy rejected the user name and password with this status.
+    AuthRejected(u8),
+    /// User name or password longer than the 255 bytes RFC 1929 allows.
+    CredentialsTooLong,
+}
+
+impl fmt::Display for Negotiation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Negotiation::Version(version) => write!(f, "unexpected version {}", version),
+            Negotiation::NoAcceptableMethods => f.write_str("no acceptable methods"),
+            Negotiation::UnofferedMethod(method) => write!(f, "unoffered method {}", method),
+            Negotiation::AuthRejected(status) => {
+                write!(f, "authentication rejected with status {}", status)
+            }
+            Negotiation::CredentialsTooLong => f.write_str("credentials too long"),
+        }
+    }
+}
+
+/// RFC 1928 reply code of a failed CONNECT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reply {
+    /// 1
+    GeneralFailure,
+    /// 2
+    NotAllowedByRuleset,
+    /// 3
+    NetworkUnreachable,
+    /// 4
+    HostUnreachable,
+    /// 5
+    ConnectionRefused,
+    /// 6
+    TtlExpired,
+    /// 7
+    CommandNotSupported,
+    /// 8
+    AddressTypeNotSupported,
+    Unknown(u8),
+}
+
+impl Reply {
+    fn from_code(code: u8) -> Reply {
+        match code {
+            1 => Reply::GeneralFailure,
+            2 => Reply::NotAllowedByRuleset,
+            3 => Reply::NetworkUnreachable,
+            4 => Reply::HostUnreachable,
+            5 => Reply::ConnectionRefused,
+            6 => Reply::TtlExpired,
+            7 => Reply::CommandNotSupported,
+            8 => Reply::AddressTypeNotSupported,
+            code => Reply::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::GeneralFailure => f.write_str("general failure"),
+            Reply::NotAllowedByRuleset => f.write_str("connection not allowed by ruleset"),
+            Reply::NetworkUnreachable => f.write_str("network unreachable"),
+            Reply::HostUnreachable => f.write_str("host unreachable"),
+            Reply::ConnectionRefused => f.write_str("connection refused"),
+            Reply::TtlExpired => f.write_str("TTL expired"),
+            Reply::CommandNotSupported => f.write_str("command not supported"),
+            Reply::AddressTypeNotSupported => f.write_str("address type not supported"),
+            Reply::Unknown(code) => write!(f, "unknown reply {}", code),
+        }
+    }
+}
+
+/// Address in a SOCKS5 request or reply.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Address {
+    /// Address of `target` as sent through `proxy`: a `socks5` proxy gets
+    /// the address the host name resolves to here, a `socks5h` proxy gets
+    /// the name and resolves it itself.
+    fn of(proxy: &Uri, target: &Uri) -> Result<Address> {
+        match target_ip(target) {
+            Some(ip) => Ok(Address::Ip(SocketAddr::new(ip, target_port(target)?))),
+            None if proxy.scheme() == "socks5h" => Ok(Address::Domain(
+                target.host_str().to_string(),
+                target_port(target)?,
+            )),
+            None => Ok(Address::Ip(target.socket_addr()?)),
+        }
+    }
+
+    fn put(&self, msg: &mut Vec<u8>) -> Result<()> {
+        let port = match self {
+            Address::Ip(SocketAddr::V4(addr)) => {
+                msg.push(ATYP_IPV4);
+                msg.extend_from_slice(&addr.ip().octets());
+                addr.port()
+            }
+            Address::Ip(SocketAddr::V6(addr)) => {
+                msg.push(ATYP_IPV6);
+                msg.extend_from_slice(&addr.ip().octets());
+                addr.port()
+            }
+            Address::Domain(host, port) => {
+                if host.is_empty() || host.len() > 255 {
+                    return Err(Error::Socks5Address(host.clone()));
+                }
+                msg.push(ATYP_DOMAIN);
+                msg.push(host.len() as u8);
+                msg.extend_from_slice(host.as_bytes());
+                *port
+            }
+        };
+        msg.extend_from_slice(&port.to_be_bytes());
+        Ok(())
+    }
+
+    async fn read<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Address> {
+        let address = match stream.read_u8().await? {
+            ATYP_IPV4 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await?;
+                Address::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), 0))
+            }
+            ATYP_IPV6 => {
+                let mut ip = [0u8; 16];
+                stream.read_exact(&mut ip).await?;
+                Address::Ip(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), 0))
+            }
+            ATYP_DOMAIN => {
+                let mut host = vec![0u8; stream.read_u8().await? as usize];
+                stream.read_exact(&mut host).await?;
+                Address::Domain(String::from_utf8_lossy(&host).to_string(), 0)
+            }
+            _ => return Err(Error::Socks5(Reply::AddressTypeNotSupported)),
+        };
+        let port = stream.read_u16().await?;
+        Ok(match address {
+            Address::Ip(addr) => Address::Ip(SocketAddr::new(addr.ip(), port)),
+            Address::Domain(host, _) => Address::Domain(host, port),
+        })
+    }
+}
+
+/// Opens a connection to `target` through the SOCKS5 `proxy`, returns it with
+/// the address the proxy bound for it. The user info of the proxy URI is sent
+/// as user name and password when the proxy asks for them.
+pub async fn connect(proxy: &Uri, target: &Uri) -> Result<(TcpStream, Address)> {
+    let mut stream = TcpStream::connect(proxy.socket_addr()?).await?;
+    let bound = handshake(&mut stream, proxy, target).await?;
+    Ok((stream, bound))
+}
+
+/// Asks the SOCKS5 `proxy` at the other end of `stream` to connect to
+/// `target`, as `connect` does, and returns the bound address.
+pub async fn handshake<S>(stream: &mut S, proxy: &Uri, target: &Uri) -> Result<Address>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Ok(authenticated_handshake(stream, proxy, target).await?.0)
+}
+
+/// `handshake`, also telling whether the proxy picked username/password
+/// authentication and accepted the credentials.
+pub(crate) async fn authenticated_handshake<S>(
+    stream: &mut S,
+    proxy: &Uri,
+    target: &Uri,
+) -> Result<(Address, bool)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let address = Address::of(proxy, target)?;
+    let credentials = user_info(proxy);
+
+    let methods: &[u8] = match credentials {
+        Some(_) => &[NO_AUTH, USERNAME_PASSWORD],
+        None => &[NO_AUTH],
+    };
+    let mut msg = vec![VERSION, methods.len() as u8];
+    msg.extend_from_slice(methods);
+    stream.write_all(&msg).await?;
+    stream.flush().await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    let authenticated = choice == [VERSION, USERNAME_PASSWORD];
+    match choice {
+        [VERSION, NO_ACCEPTABLE_METHODS] => {
+            return Err(Error::Socks5Negotiation(Negotiation::NoAcceptableMethods))
+        }
+        [VERSION, method] if !methods.contains(&method) => {
+            return Err(Error::Socks5Negotiation(Negotiation::UnofferedMethod(
+                method,
+            )))
+        }
+        [VERSION, USERNAME_PASSWORD] => {
+            let (username, password) = credentials.unwrap_or_default();
+            authenticate(stream, &username, &password).await?;
+        }
+        [VERSION, _] => (),
+        [version, _] => return Err(Error::Socks5Negotiation(Negotiation::Version(version))),
+    }
+
+    let mut msg = vec![VERSION, CONNECT, 0];
+    address.put(&mut msg)?;
+    stream.write_all(&msg).await?;
+    stream.flush().await?;
+    let mut reply = [0u8; 3];
+    stream.read_exact(&mut reply).await?;
+    match reply {
+        [VERSION, 0, _] => (),
+        [VERSION, code, _] => return Err(Error::Socks5(Reply::from_code(code))),
+        [version, ..] => return Err(Error::Socks5Negotiation(Negotiation::Version(version))),
+    }
+    Ok((Address::read(stream).await?, authenticated))
+}
+
+/// RFC 1929 username/password subnegotiation.
+async fn authenticate<S>(stream: &mut S, username: &str, password: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if username.len() > 255 || password.len() > 255 {
+        return Err(Error::Socks5Negotiation(Negotiation::CredentialsTooLong));
+    }
+    let mut msg = vec![AUTH_VERSION, username.len() as u8];
+    msg.extend_from_slice(username.as_bytes());
+    msg.push(password.len() as u8);
+    msg.extend_from_slice(password.as_bytes());
+    stream.write_all(&msg).await?;
+    stream.flush().await?;
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await?;
+    match status {
+        [_, 0] => Ok(()),
+        [_, status] => Err(Error::Socks5Negotiation(Negotiation::AuthRejected(status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Plays a SOCKS5 proxy answering with `replies` in turn, returns all it
+    /// read before each reply.
+    async fn serve(listener: TcpListener, replies: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut requests = Vec::new();
+        for reply in replies {
+            let mut buf = [0u8; 512];
+            let n = socket.read(&mut buf).await.unwrap();
+            requests.push(buf[..n].to_vec());
+            socket.write_all(&reply).await.unwrap();
+        }
+        requests
+    }
+
+    async fn proxy(replies: Vec<Vec<u8>>) -> (SocketAddr, tokio::task::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        (addr, tokio::spawn(serve(listener, replies)))
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_domain() {
+        let (addr, server) = proxy(vec![
+            vec![5, 2],
+            vec![1, 0],
+            vec![
+                5, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x1f, 0x90,
+            ],
+        ])
+        .await;
+        let proxy: Uri = format!("socks5h://user:pass@{}", addr).parse().unwrap();
+        let target: Uri = "http://unresolved.invalid:8080/".parse().unwrap();
+        let (_, bound) = connect(&proxy, &target).await.unwrap();
+
+        assert_eq!(bound, Address::Ip("[::1]:8080".parse().unwrap()));
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0], vec![5, 2, 0, 2]);
+        assert_eq!(requests[1], b"\x01\x04user\x04pass".to_vec());
+        assert_eq!(
+            requests[2],
+            b"\x05\x01\x00\x03\x12unresolved.invalid\x1f\x90".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_ip() {
+        let (addr, server) = proxy(vec![
+            vec![5, 0],
+            vec![5, 0, 0, 3, 5, b'p', b'r', b'o', b'x', b'y', 0, 80],
+        ])
+        .await;
+        let proxy: Uri = format!("socks5://{}", addr).parse().unwrap();
+        let target: Uri = "http://[2001:db8::1]:8080/".parse().unwrap();
+        let (_, bound) = connect(&proxy, &target).await.unwrap();
+
+        assert_eq!(bound, Address::Domain("proxy".to_string(), 80));
+        let requests = server.await.unwrap();
+        assert_eq!(requests[0], vec![5, 1, 0]);
+        let mut request = vec![5, 1, 0, 4, 0x20, 0x01, 0x0d, 0xb8];
+        request.extend_from_slice(&[0; 11]);
+        request.extend_from_slice(&[1, 0x1f, 0x90]);
+        assert_eq!(requests[1], request);
+    }
+
+    #[tokio::test]
+    async fn socks5_errors() {
+        let target: Uri = "http://1.2.3.4:80/".parse().unwrap();
+
+        let (addr, _) = proxy(vec![vec![5, 0xff]]).await;
+        let proxy_uri: Uri = format!("socks5://{}", addr).parse().unwrap();
+        assert_eq!(
+            connect(&proxy_uri, &target).await.unwrap_err(),
+            Error::Socks5Negotiation(Negotiation::NoAcceptableMethods)
+        );
+
+        let (addr, _) = proxy(vec![vec![5, 2], vec![1, 1]]).await;
+        let proxy_uri: Uri = format!("socks5://user:bad@{}", addr).parse().unwrap();
+        assert_eq!(
+            connect(&proxy_uri, &target).await.unwrap_err(),
+            Error::Socks5Negotiation(Negotiation::AuthRejected(1))
+        );
+
+        let (addr, _) = proxy(vec![vec![5, 0], vec![5, 2, 0]]).await;
+        let proxy_uri: Uri = format!("socks5://{}", addr).parse().unwrap();
+        assert_eq!(
+            connect(&proxy_uri, &target).await.unwrap_err(),
+            Error::Socks5(Reply::NotAllowedByRuleset)
+        );
+    }
+}
@@ -1,52 +1,292 @@
 use std::{
     fmt, io,
+    net::IpAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 
-use bytes::Bytes;
-use rsl::socks5;
+use bytes::{Bytes, BytesMut};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpStream;
-use tokio_rustls::{client::TlsStream, rustls::ClientConfig, webpki::DNSNameRef, TlsConnector};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{ClientConfig, Session},
+    webpki::DNSNameRef,
+    TlsConnector,
+};
 use uri::Uri;
 
+use crate::auth::{proxy_auth_required, Authenticator, Credentials, Target};
+use crate::decoder::{Event, ResponseDecoder};
 use crate::error::Error;
+use crate::method::Method;
+use crate::request::Request;
 use crate::response::Response;
+use crate::socks4;
+use crate::socks5;
+
+/// Byte stream a proxy hop is reached over: TCP, TLS or the tunnel through
+/// the hops before it.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
 
 pub enum MaybeHttpsStream {
     Http(TcpStream),
     Https(Box<TlsStream<TcpStream>>),
+    /// Plain connection through a chain of proxies.
+    Chain(Box<dyn Io>),
+    /// TLS connection through a chain of proxies.
+    ChainTls(Box<TlsStream<Box<dyn Io>>>),
 }
 
 impl MaybeHttpsStream {
     pub async fn new(uri: &Uri) -> Result<Self, Error> {
         let addr = uri.socket_addr()?;
         let stream = TcpStream::connect(addr).await?;
-        MaybeHttpsStream::maybe_ssl(uri, stream).await
+        MaybeHttpsStream::maybe_ssl(uri, stream, &[]).await
+    }
+
+    /// Opens a connection to `uri`, either direct or through `proxy`. An `http`
+    /// or `https` proxy gets a connection of its own, the request itself
+    /// addresses the target, except for `https` targets which are tunnelled
+    /// with CONNECT.
+    pub async fn connect(uri: &Uri, proxy: Option<&Uri>) -> Result<Self, Error> {
+        MaybeHttpsStream::connect_alpn(uri, proxy, &[]).await
+    }
+
+    /// Same as `connect`, offering `alpn` protocols in the TLS handshake with
+    /// the target.
+    pub async fn connect_alpn(
+        uri: &Uri,
+        proxy: Option<&Uri>,
+        alpn: &[Vec<u8>],
+    ) -> Result<Self, Error> {
+        let credentials = proxy.and_then(Credentials::from_uri);
+        let auth = credentials.as_ref().map(|auth| auth as &dyn Authenticator);
+        MaybeHttpsStream::connect_auth(uri, proxy, alpn, auth).await
+    }
+
+    /// Same as `connect_alpn`, `auth` answers an `http` proxy asking for
+    /// credentials on CONNECT.
+    pub async fn connect_auth(
+        uri: &Uri,
+        proxy: Option<&Uri>,
+        alpn: &[Vec<u8>],
+        auth: Option<&dyn Authenticator>,
+    ) -> Result<Self, Error> {
+        match proxy {
+            Some(proxy) => match proxy.scheme() {
+                "socks4" | "socks4a" | "socks5" | "socks5h" => {
+                    let stream = MaybeHttpsStream::socks_stream(proxy, uri).await?;
+                    MaybeHttpsStream::maybe_ssl(uri, stream, alpn).await
+                }
+                "http" if uri.is_ssl() => {
+                    let stream = MaybeHttpsStream::tunnel_auth(proxy, uri, auth).await?;
+                    MaybeHttpsStream::maybe_ssl(uri, stream, alpn).await
+                }
+                "https" if uri.is_ssl() => {
+                    MaybeHttpsStream::tls_tunnel(proxy, uri, alpn, auth).await
+                }
+                "http" | "https" => MaybeHttpsStream::new(proxy).await,
+                scheme => Err(Error::UnsupportedProxyScheme(scheme.to_owned())),
+            },
+            None => {
+                let stream = TcpStream::connect(uri.socket_addr()?).await?;
+                MaybeHttpsStream::maybe_ssl(uri, stream, alpn).await
+            }
+        }
     }
 
+    /// Opens a connection to `target` through a SOCKS4, SOCKS4a or SOCKS5
+    /// `proxy`, with TLS on top for an `https` target.
     pub async fn socks(proxy: &Uri, target: &Uri) -> Result<Self, Error> {
-        let stream = socks5::connect(proxy.as_str(), target.as_str()).await?;
-        MaybeHttpsStream::maybe_ssl(target, stream).await
+        let stream = MaybeHttpsStream::socks_stream(proxy, target).await?;
+        MaybeHttpsStream::maybe_ssl(target, stream, &[]).await
     }
 
-    async fn maybe_ssl(uri: &Uri, stream: TcpStream) -> Result<Self, Error> {
+    async fn socks_stream(proxy: &Uri, target: &Uri) -> Result<TcpStream, Error> {
+        match proxy.scheme() {
+            "socks4" | "socks4a" => socks4::connect(proxy, target).await,
+            _ => Ok(socks5::connect(proxy, target).await?.0),
+        }
+    }
+
+    /// Asks an http proxy to open a CONNECT tunnel to `target`, answering a
+    /// 407 with the credentials of the proxy URI.
+    pub async fn tunnel(proxy: &Uri, target: &Uri) -> Result<TcpStream, Error> {
+        let credentials = Credentials::from_uri(proxy);
+        let auth = credentials.as_ref().map(|auth| auth as &dyn Authenticator);
+        MaybeHttpsStream::tunnel_auth(proxy, target, auth).await
+    }
+
+    /// Same as `tunnel` with `auth` answering the proxy. When it takes up the
+    /// challenge of a 407, CONNECT is sent once more on a new connection, a
+    /// 407 left unanswered fails with `Error::ProxyAuthRequired`.
+    pub async fn tunnel_auth(
+        proxy: &Uri,
+        target: &Uri,
+        auth: Option<&dyn Authenticator>,
+    ) -> Result<TcpStream, Error> {
+        let mut retry = true;
+        loop {
+            let mut stream = TcpStream::connect(proxy.socket_addr()?).await?;
+            if http_connect(&mut stream, target, auth, retry).await? {
+                return Ok(stream);
+            }
+            retry = false;
+        }
+    }
+
+    /// Opens a TLS connection to `target` inside a CONNECT tunnel through the
+    /// `https` proxy, itself reached over TLS. `auth` answers the proxy as
+    /// with `tunnel_auth`.
+    async fn tls_tunnel(
+        proxy: &Uri,
+        target: &Uri,
+        alpn: &[Vec<u8>],
+        auth: Option<&dyn Authenticator>,
+    ) -> Result<Self, Error> {
+        let mut retry = true;
+        loop {
+            let stream: Box<dyn Io> = Box::new(TcpStream::connect(proxy.socket_addr()?).await?);
+            if let Some(stream) =
+                MaybeHttpsStream::hop(stream, proxy, target, auth, retry, false).await?
+            {
+                let stream = tls(target, stream, alpn).await?;
+                return Ok(MaybeHttpsStream::ChainTls(Box::new(stream)));
+            }
+            retry = false;
+        }
+    }
+
+    /// Opens a connection to `uri` through `chain`, each proxy tunnelled
+    /// through the ones before it with the credentials of its own URI. The
+    /// last proxy is used as `connect_auth` uses `proxy`, with `auth`. A hop
+    /// failing is reported as `Error::ProxyHop` with its place in the chain,
+    /// counted from 1.
+    pub async fn connect_chain(
+        uri: &Uri,
+        chain: &[Uri],
+        alpn: &[Vec<u8>],
+        auth: Option<&dyn Authenticator>,
+    ) -> Result<Self, Error> {
+        if chain.len() < 2 {
+            return MaybeHttpsStream::connect_auth(uri, chain.first(), alpn, auth).await;
+        }
+        let credentials: Vec<Option<Credentials>> = chain[..chain.len() - 1]
+            .iter()
+            .map(Credentials::from_uri)
+            .collect();
+        let mut retry = vec![true; chain.len()];
+        let stream = loop {
+            if let Some(stream) =
+                MaybeHttpsStream::open_chain(uri, chain, &credentials, auth, &mut retry).await?
+            {
+                break stream;
+            }
+        };
+        if uri.is_ssl() && !forwards(&chain[chain.len() - 1], uri) {
+            let stream = tls(uri, stream, alpn).await?;
+            Ok(MaybeHttpsStream::ChainTls(Box::new(stream)))
+        } else {
+            Ok(MaybeHttpsStream::Chain(stream))
+        }
+    }
+
+    /// One attempt at `connect_chain`, `None` when a hop took up a challenge
+    /// and is to be passed again on a new connection.
+    async fn open_chain(
+        uri: &Uri,
+        chain: &[Uri],
+        credentials: &[Option<Credentials>],
+        auth: Option<&dyn Authenticator>,
+        retry: &mut [bool],
+    ) -> Result<Option<Box<dyn Io>>, Error> {
+        let first = async { Ok::<_, Error>(TcpStream::connect(chain[0].socket_addr()?).await?) };
+        let first = first
+            .await
+            .map_err(|err| Error::ProxyHop(1, Box::new(err)))?;
+        let mut stream: Box<dyn Io> = Box::new(first);
+        for (hop, proxy) in chain.iter().enumerate() {
+            let (target, auth) = match chain.get(hop + 1) {
+                Some(next) => (
+                    next,
+                    credentials[hop]
+                        .as_ref()
+                        .map(|auth| auth as &dyn Authenticator),
+                ),
+                None => (uri, auth),
+            };
+            let forward = hop + 1 == chain.len() && forwards(proxy, uri);
+            let next = MaybeHttpsStream::hop(stream, proxy, target, auth, retry[hop], forward)
+                .await
+                .map_err(|err| Error::ProxyHop(hop + 1, Box::new(err)))?;
+            match next {
+                Some(next) => stream = next,
+                None => {
+                    retry[hop] = false;
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(stream))
+    }
+
+    /// Passes `proxy` on `stream`, which reaches it, towards `target`. Gives
+    /// `None` when a challenge was taken up and the chain is to be opened
+    /// again. With `forward` the requests are sent to `proxy` itself.
+    async fn hop(
+        mut stream: Box<dyn Io>,
+        proxy: &Uri,
+        target: &Uri,
+        auth: Option<&dyn Authenticator>,
+        retry: bool,
+        forward: bool,
+    ) -> Result<Option<Box<dyn Io>>, Error> {
+        if proxy.scheme() == "https" {
+            stream = Box::new(tls(proxy, stream, &[]).await?);
+        }
+        match proxy.scheme() {
+            "socks4" | "socks4a" => socks4::handshake(&mut stream, proxy, target).await?,
+            "socks5" | "socks5h" => {
+                socks5::handshake(&mut stream, proxy, target).await?;
+            }
+            "http" | "https" if forward => (),
+            "http" | "https" => {
+                if !http_connect(&mut stream, target, auth, retry).await? {
+                    return Ok(None);
+                }
+            }
+            scheme => return Err(Error::UnsupportedProxyScheme(scheme.to_owned())),
+        }
+        Ok(Some(stream))
+    }
+
+    pub(crate) async fn maybe_ssl(
+        uri: &Uri,
+        stream: TcpStream,
+        alpn: &[Vec<u8>],
+    ) -> Result<Self, Error> {
         if uri.is_ssl() {
-            let mut config = ClientConfig::new();
-            config
-                .root_store
-                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-            let connector = TlsConnector::from(Arc::new(config));
-            let dns_name = DNSNameRef::try_from_ascii_str(uri.host_str())?;
-            let stream = connector.connect(dns_name, stream).await?;
+            let stream = tls(uri, stream, alpn).await?;
             Ok(MaybeHttpsStream::from(stream))
         } else {
             Ok(MaybeHttpsStream::from(stream))
         }
     }
 
+    /// Protocol picked by the server during ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<Vec<u8>> {
+        match self {
+            MaybeHttpsStream::Http(_) => None,
+            MaybeHttpsStream::Https(s) => s.get_ref().1.get_alpn_protocol().map(|p| p.to_vec()),
+            MaybeHttpsStream::Chain(_) => None,
+            MaybeHttpsStream::ChainTls(s) => s.get_ref().1.get_alpn_protocol().map(|p| p.to_vec()),
+        }
+    }
+
     pub async fn get_body(&mut self, content_len: usize) -> Result<Bytes, Error> {
         let mut body = vec![0u8; content_len];
         self.read_exact(&mut body).await?;
@@ -54,21 +294,36 @@ impl MaybeHttpsStream {
     }
 
     pub async fn get_response(&mut self) -> Result<Response, Error> {
-        let mut header = Vec::with_capacity(512);
-        while !(header.len() > 4 && header[header.len() - 4..] == b"\r\n\r\n"[..]) {
-            header.push(self.read_u8().await.or(Err(Error::HeaderIncomplete))?);
-            if header.len() > 1024 {
-                return Err(Error::HeaderToBig);
+        self.read_response(ResponseDecoder::new()).await
+    }
+
+    /// Reads one response with `decoder`. Bytes a server sends after a `101`
+    /// already belong to the new protocol and are given as the body, after
+    /// any other response they fail with `Error::UnexpectedData`.
+    pub async fn read_response(&mut self, mut decoder: ResponseDecoder) -> Result<Response, Error> {
+        let mut buf = [0u8; 4096];
+        let mut response = None;
+        let mut body = BytesMut::new();
+        loop {
+            match decoder.decode()? {
+                Some(Event::Head(head)) => response = Some(head),
+                Some(Event::Body(chunk)) => body.extend_from_slice(&chunk),
+                Some(Event::End) => break,
+                None => match self.read(&mut buf).await? {
+                    0 => decoder.finish(),
+                    n => decoder.feed(&buf[..n]),
+                },
             }
         }
-        let response = Response::from_header(&header)?;
-        let content_len = response.content_len()?;
-        let body = self.get_body(content_len).await?;
-        Ok(Response {
-            status: response.status,
-            headers: response.headers,
-            body,
-        })
+        let mut response = response.ok_or(Error::EmptyResponse)?;
+        let rest = decoder.remaining();
+        if u16::from(response.status_code()) == 101 {
+            body.extend_from_slice(rest);
+        } else if rest.iter().any(|byte| !matches!(byte, b'\r' | b'\n')) {
+            return Err(Error::UnexpectedData(rest.len()));
+        }
+        response.body = body.freeze();
+        Ok(response)
     }
 
     pub async fn send_msg(&mut self, msg: &[u8]) -> Result<(), Error> {
@@ -83,6 +338,8 @@ impl fmt::Debug for MaybeHttpsStream {
         match self {
             MaybeHttpsStream::Http(s) => f.debug_tuple("Http").field(s).finish(),
             MaybeHttpsStream::Https(s) => f.debug_tuple("Https").field(s).finish(),
+            MaybeHttpsStream::Chain(_) => f.debug_tuple("Chain").finish(),
+            MaybeHttpsStream::ChainTls(_) => f.debug_tuple("ChainTls").finish(),
         }
     }
 }
@@ -99,6 +356,100 @@ impl From<TlsStream<TcpStream>> for MaybeHttpsStream {
     }
 }
 
+/// Fails with `Error::LocalDns` when connecting to `uri` through `proxy`
+/// would resolve the host name of `uri` here, as `socks4` and `socks5` proxies
+/// do. Without a proxy, or when `uri` holds an IP address, it never fails.
+pub fn check_no_local_dns(uri: &Uri, proxy: Option<&Uri>) -> Result<(), Error> {
+    match proxy.map(|proxy| proxy.scheme()) {
+        Some("socks4") | Some("socks5") if target_ip(uri).is_none() => {
+            Err(Error::LocalDns(uri.host_str().to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Address of `uri` when its host is an IP literal rather than a name.
+pub(crate) fn target_ip(uri: &Uri) -> Option<IpAddr> {
+    let host = uri.host_str().trim_start_matches('[').trim_end_matches(']');
+    host.parse().ok()
+}
+
+/// Port of `uri`, taken from its authority so the host is not resolved.
+pub(crate) fn target_port(uri: &Uri) -> Result<u16, Error> {
+    let host_port = uri
+        .host_port()
+        .map_or(String::new(), |host_port| host_port.to_string());
+    let port = host_port.rsplit(':').next().unwrap_or_default();
+    Ok(port.parse()?)
+}
+
+/// Whether requests to `uri` are sent to `proxy` itself rather than through
+/// a tunnel, as for `http` and `https` proxies except with `https` targets.
+fn forwards(proxy: &Uri, uri: &Uri) -> bool {
+    matches!(proxy.scheme(), "http" | "https") && !uri.is_ssl()
+}
+
+async fn tls<S: Io>(uri: &Uri, stream: S, alpn: &[Vec<u8>]) -> Result<TlsStream<S>, Error> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    config.alpn_protocols = alpn.to_vec();
+    let connector = TlsConnector::from(Arc::new(config));
+    let dns_name = DNSNameRef::try_from_ascii_str(uri.host_str())?;
+    Ok(connector.connect(dns_name, stream).await?)
+}
+
+/// Sends CONNECT for `target` over `stream`, which reaches an http proxy.
+/// Gives `Ok(false)` when `retry` is set and `auth` takes up the challenge
+/// of a 407, CONNECT is then to be sent again on a new connection.
+pub(crate) async fn http_connect<S: Io>(
+    stream: &mut S,
+    target: &Uri,
+    auth: Option<&dyn Authenticator>,
+    retry: bool,
+) -> Result<bool, Error> {
+    let host_port = target
+        .host_port()
+        .map_or(String::new(), |host_port| host_port.to_string());
+    let mut request = Request::from_header(
+        format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", host_port).as_bytes(),
+    )?;
+    let mut sent = request.clone();
+    if let Some(auth) = auth {
+        auth.before_send(&mut sent, Target::Proxy)?;
+    }
+    stream.write_all(&sent.to_vec()).await?;
+    stream.flush().await?;
+    let response = connect_response(stream).await?;
+    match u16::from(response.status_code()) {
+        _ if response.status_code().is_success() => Ok(true),
+        407 => match auth {
+            Some(auth) if retry && auth.on_challenge(&mut request, &response, Target::Proxy)? => {
+                Ok(false)
+            }
+            _ => Err(proxy_auth_required(&response)),
+        },
+        code => Err(Error::TunnelFailed(code)),
+    }
+}
+
+/// Head of the proxy response to CONNECT.
+async fn connect_response<S: Io>(stream: &mut S) -> Result<Response, Error> {
+    let mut decoder = ResponseDecoder::with_method(&Method::CONNECT);
+    let mut buf = [0u8; 1024];
+    loop {
+        match decoder.decode()? {
+            Some(Event::Head(response)) => return Ok(response),
+            Some(_) => return Err(Error::EmptyResponse),
+            None => match stream.read(&mut buf).await? {
+                0 => decoder.finish(),
+                n => decoder.feed(&buf[..n]),
+            },
+        }
+    }
+}
+
 impl AsyncRead for MaybeHttpsStream {
     // // #[inline]
     // unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [std::mem::MaybeUninit<u8>]) -> bool {
@@ -117,6 +468,8 @@ impl AsyncRead for MaybeHttpsStream {
         match Pin::get_mut(self) {
             MaybeHttpsStream::Http(s) => Pin::new(s).poll_read(cx, buf),
             MaybeHttpsStream::Https(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeHttpsStream::Chain(s) => Pin::new(s).poll_read(cx, buf),
+            MaybeHttpsStream::ChainTls(s) => Pin::new(s).poll_read(cx, buf),
         }
     }
 
@@ -143,6 +496,8 @@ impl AsyncWrite for MaybeHttpsStream {
         match Pin::get_mut(self) {
             MaybeHttpsStream::Http(s) => Pin::new(s).poll_write(cx, buf),
             MaybeHttpsStream::Https(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeHttpsStream::Chain(s) => Pin::new(s).poll_write(cx, buf),
+            MaybeHttpsStream::ChainTls(s) => Pin::new(s).poll_write(cx, buf),
         }
     }
 
@@ -151,6 +506,8 @@ impl AsyncWrite for MaybeHttpsStream {
         match Pin::get_mut(self) {
             MaybeHttpsStream::Http(s) => Pin::new(s).poll_flush(cx),
             MaybeHttpsStream::Https(s) => Pin::new(s).poll_flush(cx),
+            MaybeHttpsStream::Chain(s) => Pin::new(s).poll_flush(cx),
+            MaybeHttpsStream::ChainTls(s) => Pin::new(s).poll_flush(cx),
         }
     }
 
@@ -159,6 +516,8 @@ impl AsyncWrite for MaybeHttpsStream {
         match Pin::get_mut(self) {
             MaybeHttpsStream::Http(s) => Pin::new(s).poll_shutdown(cx),
             MaybeHttpsStream::Https(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeHttpsStream::Chain(s) => Pin::new(s).poll_shutdown(cx),
+            MaybeHttpsStream::ChainTls(s) => Pin::new(s).poll_shutdown(cx),
         }
     }
 
@@ -251,4 +610,110 @@ mod tests {
         let body = String::from_utf8(buf).unwrap();
         assert!(&body.contains(crate::tests::IP.as_str()));
     }
+
+    #[tokio::test]
+    async fn tunnel_challenge() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in [
+                "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Digest realm=\"proxy\", nonce=\"abc\"\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 Connection established\r\n\r\n",
+                "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"proxy\"\r\n\r\n",
+            ]
+            .iter()
+            {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut header = Vec::new();
+                while !header.ends_with(b"\r\n\r\n") {
+                    header.push(socket.read_u8().await.unwrap());
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(header).unwrap());
+            }
+            requests
+        });
+
+        let target: Uri = "https://api.ipify.org/".parse().unwrap();
+        let proxy: Uri = format!("http://user:pass@{}", addr).parse().unwrap();
+        MaybeHttpsStream::tunnel(&proxy, &target).await.unwrap();
+        let proxy: Uri = format!("http://{}", addr).parse().unwrap();
+        assert_eq!(
+            MaybeHttpsStream::tunnel(&proxy, &target).await.unwrap_err(),
+            Error::ProxyAuthRequired {
+                scheme: "Basic".to_string(),
+                realm: Some("proxy".to_string()),
+            }
+        );
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("CONNECT api.ipify.org:443 HTTP/1.1\r\n"));
+        assert!(!requests[0].contains("proxy-authorization:"));
+        assert!(requests[1].contains(
+            "proxy-authorization: Digest username=\"user\", realm=\"proxy\", uri=\"api.ipify.org:443\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_response_rest() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            for response in [
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\nframe",
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nokHTTP/1.1 200 OK\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok\r\n",
+            ]
+            .iter()
+            {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut stream = MaybeHttpsStream::new(&uri).await.unwrap();
+        let response = stream.get_response().await.unwrap();
+        assert_eq!(u16::from(response.status_code()), 101);
+        assert_eq!(response.body(), &b"frame"[..]);
+        let mut stream = MaybeHttpsStream::new(&uri).await.unwrap();
+        assert_eq!(
+            stream.get_response().await.unwrap_err(),
+            Error::UnexpectedData(19)
+        );
+        let mut stream = MaybeHttpsStream::new(&uri).await.unwrap();
+        assert_eq!(stream.get_response().await.unwrap().body(), &b"ok"[..]);
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn no_local_dns() {
+        let uri: Uri = "https://api.ipify.org/".parse().unwrap();
+        let ip: Uri = "https://1.1.1.1/".parse().unwrap();
+        let proxy = |proxy: &str| proxy.parse::<Uri>().unwrap();
+
+        assert_eq!(check_no_local_dns(&uri, None), Ok(()));
+        assert_eq!(
+            check_no_local_dns(&uri, Some(&proxy("socks5://127.0.0.1:1080"))),
+            Err(Error::LocalDns("api.ipify.org".to_string()))
+        );
+        assert_eq!(
+            check_no_local_dns(&uri, Some(&proxy("socks4://127.0.0.1:1080"))),
+            Err(Error::LocalDns("api.ipify.org".to_string()))
+        );
+        assert_eq!(
+            check_no_local_dns(&ip, Some(&proxy("socks5://127.0.0.1:1080"))),
+            Ok(())
+        );
+        for scheme in ["socks5h", "socks4a", "http"].iter() {
+            let proxy = proxy(&format!("{}://127.0.0.1:1080", scheme));
+            assert_eq!(check_no_local_dns(&uri, Some(&proxy)), Ok(()));
+        }
+    }
 }
@@ -0,0 +1,1117 @@
+use std::{
+    convert::TryFrom,
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{decode, encode};
+
+use crate::error::{Error, Result};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Header with a typed value, read with `Headers::typed` and written with
+/// `Headers::set_typed`.
+pub trait Header: Sized {
+    fn name() -> &'static str;
+
+    /// Parses the field value, fails with `Error::InvalidHeader`.
+    fn parse(value: &str) -> Result<Self>;
+
+    /// Field value as sent on the wire.
+    fn value(&self) -> String;
+}
+
+/// `Content-Type`, the media type is kept in lower case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+    pub mime: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub fn new(mime: &str) -> ContentType {
+        ContentType {
+            mime: mime.to_ascii_lowercase(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: &str) -> ContentType {
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.get_param("charset")
+    }
+}
+
+impl Header for ContentType {
+    fn name() -> &'static str {
+        "Content-Type"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut parts = split_quoted(value, ';').into_iter();
+        let mime = parts.next().unwrap_or_default();
+        let mut split = mime.splitn(2, '/');
+        match (split.next(), split.next()) {
+            (Some(kind), Some(subtype)) if is_token(kind) && is_token(subtype) => (),
+            _ => return Err(invalid::<Self>()),
+        }
+        let mut content_type = ContentType::new(mime);
+        for param in parts {
+            let (name, value) = parse_param::<Self>(param)?;
+            content_type = content_type.param(&name, &value);
+        }
+        Ok(content_type)
+    }
+
+    fn value(&self) -> String {
+        let mut value = self.mime.clone();
+        for (name, param) in self.params.iter() {
+            let _ = write!(value, "; {}={}", name, quote(param));
+        }
+        value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentLength(pub u64);
+
+impl Header for ContentLength {
+    fn name() -> &'static str {
+        "Content-Length"
+    }
+
+    /// Repeated identical values, as left by a proxy, are accepted.
+    fn parse(value: &str) -> Result<Self> {
+        let mut lengths = split_quoted(value, ',').into_iter().map(|len| {
+            if len.bytes().all(|b| b.is_ascii_digit()) {
+                len.parse::<u64>().map_err(|_| invalid::<Self>())
+            } else {
+                Err(invalid::<Self>())
+            }
+        });
+        let len = lengths.next().ok_or_else(invalid::<Self>)??;
+        for other in lengths {
+            if other? != len {
+                return Err(invalid::<Self>());
+            }
+        }
+        Ok(ContentLength(len))
+    }
+
+    fn value(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Codings in the order they were applied, in lower case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentEncoding(pub Vec<String>);
+
+impl Header for ContentEncoding {
+    fn name() -> &'static str {
+        "Content-Encoding"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        Ok(ContentEncoding(parse_codings::<Self>(value)?))
+    }
+
+    fn value(&self) -> String {
+        self.0.join(", ")
+    }
+}
+
+/// Codings in the order they were applied, in lower case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferEncoding(pub Vec<String>);
+
+impl TransferEncoding {
+    pub fn chunked() -> TransferEncoding {
+        TransferEncoding(vec!["chunked".to_string()])
+    }
+
+    /// Whether the message is framed by chunks, which must be the last coding.
+    pub fn is_chunked(&self) -> bool {
+        self.0.last().map(String::as_str) == Some("chunked")
+    }
+}
+
+impl Header for TransferEncoding {
+    fn name() -> &'static str {
+        "Transfer-Encoding"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        Ok(TransferEncoding(parse_codings::<Self>(value)?))
+    }
+
+    fn value(&self) -> String {
+        self.0.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Date(pub SystemTime);
+
+impl Header for Date {
+    fn name() -> &'static str {
+        "Date"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        parse_http_date(value).map(Date).ok_or_else(invalid::<Self>)
+    }
+
+    fn value(&self) -> String {
+        format_http_date(self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LastModified(pub SystemTime);
+
+impl Header for LastModified {
+    fn name() -> &'static str {
+        "Last-Modified"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        parse_http_date(value)
+            .map(LastModified)
+            .ok_or_else(invalid::<Self>)
+    }
+
+    fn value(&self) -> String {
+        format_http_date(self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expires(pub SystemTime);
+
+impl Header for Expires {
+    fn name() -> &'static str {
+        "Expires"
+    }
+
+    /// An invalid date, such as "0", means already expired (RFC 9111
+    /// section 5.3) and is read as the Unix epoch.
+    fn parse(value: &str) -> Result<Self> {
+        Ok(Expires(parse_http_date(value).unwrap_or(UNIX_EPOCH)))
+    }
+
+    fn value(&self) -> String {
+        format_http_date(self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ETag {
+    pub tag: String,
+    pub weak: bool,
+}
+
+impl ETag {
+    pub fn strong(tag: &str) -> ETag {
+        ETag {
+            tag: tag.to_string(),
+            weak: false,
+        }
+    }
+
+    pub fn weak(tag: &str) -> ETag {
+        ETag {
+            tag: tag.to_string(),
+            weak: true,
+        }
+    }
+
+    /// Strong comparison of RFC 9110 section 8.8.3.2, used for ranges.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison, used for `If-None-Match`.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Header for ETag {
+    fn name() -> &'static str {
+        "ETag"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (weak, tag) = match value.strip_prefix("W/") {
+            Some(tag) => (true, tag),
+            None => (false, value),
+        };
+        if tag.len() < 2 || !tag.starts_with('"') || !tag.ends_with('"') {
+            return Err(invalid::<Self>());
+        }
+        let tag = &tag[1..tag.len() - 1];
+        if tag.contains('"') {
+            return Err(invalid::<Self>());
+        }
+        Ok(ETag {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+
+    fn value(&self) -> String {
+        format!("{}\"{}\"", if self.weak { "W/" } else { "" }, self.tag)
+    }
+}
+
+/// `Cache-Control` directives of requests and responses. Unknown directives
+/// are kept in `extensions`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheControl {
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub max_stale: Option<Option<u64>>,
+    pub min_fresh: Option<u64>,
+    pub stale_while_revalidate: Option<u64>,
+    pub stale_if_error: Option<u64>,
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub no_transform: bool,
+    pub only_if_cached: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub must_understand: bool,
+    pub public: bool,
+    pub private: bool,
+    pub immutable: bool,
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl Header for CacheControl {
+    fn name() -> &'static str {
+        "Cache-Control"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut cache_control = CacheControl::default();
+        for directive in split_quoted(value, ',') {
+            let mut split = directive.splitn(2, '=');
+            let name = split.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let argument = split.next().map(|argument| unquote(argument.trim()));
+            if !is_token(&name) {
+                return Err(invalid::<Self>());
+            }
+            let seconds = || -> Result<u64> {
+                argument
+                    .as_ref()
+                    .and_then(|argument| argument.parse().ok())
+                    .ok_or_else(invalid::<Self>)
+            };
+            match name.as_str() {
+                "max-age" => cache_control.max_age = Some(seconds()?),
+                "s-maxage" => cache_control.s_maxage = Some(seconds()?),
+                "max-stale" if argument.is_none() => cache_control.max_stale = Some(None),
+                "max-stale" => cache_control.max_stale = Some(Some(seconds()?)),
+                "min-fresh" => cache_control.min_fresh = Some(seconds()?),
+                "stale-while-revalidate" => cache_control.stale_while_revalidate = Some(seconds()?),
+                "stale-if-error" => cache_control.stale_if_error = Some(seconds()?),
+                "no-cache" => cache_control.no_cache = true,
+                "no-store" => cache_control.no_store = true,
+                "no-transform" => cache_control.no_transform = true,
+                "only-if-cached" => cache_control.only_if_cached = true,
+                "must-revalidate" => cache_control.must_revalidate = true,
+                "proxy-revalidate" => cache_control.proxy_revalidate = true,
+                "must-understand" => cache_control.must_understand = true,
+                "public" => cache_control.public = true,
+                "private" => cache_control.private = true,
+                "immutable" => cache_control.immutable = true,
+                _ => cache_control.extensions.push((name, argument)),
+            }
+        }
+        Ok(cache_control)
+    }
+
+    fn value(&self) -> String {
+        let mut directives = Vec::new();
+        let flags = [
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.only_if_cached, "only-if-cached"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.must_understand, "must-understand"),
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.immutable, "immutable"),
+        ];
+        for (set, name) in flags.iter() {
+            if *set {
+                directives.push(name.to_string());
+            }
+        }
+        let seconds = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.max_stale.unwrap_or(None), "max-stale"),
+            (self.min_fresh, "min-fresh"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+        for (seconds, name) in seconds.iter() {
+            if let Some(seconds) = seconds {
+                directives.push(format!("{}={}", name, seconds));
+            }
+        }
+        if self.max_stale == Some(None) {
+            directives.push("max-stale".to_string());
+        }
+        for (name, argument) in self.extensions.iter() {
+            match argument {
+                Some(argument) => directives.push(format!("{}={}", name, quote(argument))),
+                None => directives.push(name.clone()),
+            }
+        }
+        directives.join(", ")
+    }
+}
+
+/// Target of a redirect or of a created resource, which may be relative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location(pub String);
+
+impl Header for Location {
+    fn name() -> &'static str {
+        "Location"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(invalid::<Self>());
+        }
+        Ok(Location(value.to_string()))
+    }
+
+    fn value(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryAfter {
+    Delay(Duration),
+    Date(SystemTime),
+}
+
+impl RetryAfter {
+    /// Time left to wait from `now`, zero for a date in the past.
+    pub fn delay_from(&self, now: SystemTime) -> Duration {
+        match self {
+            RetryAfter::Delay(delay) => *delay,
+            RetryAfter::Date(date) => date.duration_since(now).unwrap_or_default(),
+        }
+    }
+}
+
+impl Header for RetryAfter {
+    fn name() -> &'static str {
+        "Retry-After"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            let seconds = value.parse().map_err(|_| invalid::<Self>())?;
+            return Ok(RetryAfter::Delay(Duration::from_secs(seconds)));
+        }
+        parse_http_date(value)
+            .map(RetryAfter::Date)
+            .ok_or_else(invalid::<Self>)
+    }
+
+    fn value(&self) -> String {
+        match self {
+            RetryAfter::Delay(delay) => delay.as_secs().to_string(),
+            RetryAfter::Date(date) => format_http_date(*date),
+        }
+    }
+}
+
+/// One link of a `Link` header (RFC 8288).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkValue {
+    pub uri: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl LinkValue {
+    pub fn new(uri: &str) -> LinkValue {
+        LinkValue {
+            uri: uri.to_string(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: &str) -> LinkValue {
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether `rel`, a space separated list, holds `relation`.
+    pub fn has_rel(&self, relation: &str) -> bool {
+        self.get_param("rel")
+            .map(|rel| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case(relation))
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link(pub Vec<LinkValue>);
+
+impl Link {
+    /// First link with the relation `rel`, e.g. "next" when paging.
+    pub fn find(&self, rel: &str) -> Option<&LinkValue> {
+        self.0.iter().find(|link| link.has_rel(rel))
+    }
+}
+
+impl Header for Link {
+    fn name() -> &'static str {
+        "Link"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut links = Vec::new();
+        for link in split_quoted(value, ',') {
+            let mut parts = split_quoted(link, ';').into_iter();
+            let uri = parts.next().unwrap_or_default();
+            if uri.len() < 2 || !uri.starts_with('<') || !uri.ends_with('>') {
+                return Err(invalid::<Self>());
+            }
+            let mut link = LinkValue::new(&uri[1..uri.len() - 1]);
+            for param in parts {
+                let (name, value) = parse_param::<Self>(param)?;
+                link = link.param(&name, &value);
+            }
+            links.push(link);
+        }
+        Ok(Link(links))
+    }
+
+    fn value(&self) -> String {
+        self.0
+            .iter()
+            .map(|link| {
+                let mut value = format!("<{}>", link.uri);
+                for (name, param) in link.params.iter() {
+                    let _ = write!(value, "; {}={}", name, quote(param));
+                }
+                value
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+/// Authentication scheme with either a token68 or parameters, the shape of
+/// both challenges and credentials (RFC 9110 section 11).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub scheme: String,
+    pub token68: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn new(scheme: &str) -> Challenge {
+        Challenge {
+            scheme: scheme.to_string(),
+            token68: None,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: &str) -> Challenge {
+        self.params
+            .push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn realm(&self) -> Option<&str> {
+        self.get_param("realm")
+    }
+
+    fn format(&self) -> String {
+        let mut value = self.scheme.clone();
+        if let Some(token68) = &self.token68 {
+            value.push(' ');
+            value.push_str(token68);
+        }
+        let params = self
+            .params
+            .iter()
+            .map(|(name, param)| format!("{}={}", name, quote(param)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        if !params.is_empty() {
+            value.push(' ');
+            value.push_str(&params);
+        }
+        value
+    }
+}
+
+/// Challenges of a 401 response, one field may hold several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WwwAuthenticate(pub Vec<Challenge>);
+
+impl WwwAuthenticate {
+    pub fn find(&self, scheme: &str) -> Option<&Challenge> {
+        self.0
+            .iter()
+            .find(|challenge| challenge.scheme.eq_ignore_ascii_case(scheme))
+    }
+}
+
+impl Header for WwwAuthenticate {
+    fn name() -> &'static str {
+        "WWW-Authenticate"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        parse_challenges::<Self>(value).map(WwwAuthenticate)
+    }
+
+    fn value(&self) -> String {
+        self.0
+            .iter()
+            .map(Challenge::format)
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authorization(pub Challenge);
+
+impl Authorization {
+    pub fn basic(username: &str, password: &str) -> Authorization {
+        let mut credentials = Challenge::new("Basic");
+        credentials.token68 = Some(encode(format!("{}:{}", username, password)));
+        Authorization(credentials)
+    }
+
+    pub fn bearer(token: &str) -> Authorization {
+        let mut credentials = Challenge::new("Bearer");
+        credentials.token68 = Some(token.to_string());
+        Authorization(credentials)
+    }
+
+    /// User name and password of `Basic` credentials.
+    pub fn basic_credentials(&self) -> Option<(String, String)> {
+        if !self.0.scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+        let decoded = decode(self.0.token68.as_ref()?).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let idx = decoded.find(':')?;
+        Some((decoded[..idx].to_string(), decoded[idx + 1..].to_string()))
+    }
+
+    pub fn bearer_token(&self) -> Option<&str> {
+        if self.0.scheme.eq_ignore_ascii_case("Bearer") {
+            self.0.token68.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+impl Header for Authorization {
+    fn name() -> &'static str {
+        "Authorization"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        let mut challenges = parse_challenges::<Self>(value)?;
+        if challenges.len() != 1 {
+            return Err(invalid::<Self>());
+        }
+        Ok(Authorization(challenges.remove(0)))
+    }
+
+    fn value(&self) -> String {
+        self.0.format()
+    }
+}
+
+/// Challenges of a 407 response from a proxy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyAuthenticate(pub Vec<Challenge>);
+
+impl ProxyAuthenticate {
+    pub fn find(&self, scheme: &str) -> Option<&Challenge> {
+        self.0
+            .iter()
+            .find(|challenge| challenge.scheme.eq_ignore_ascii_case(scheme))
+    }
+}
+
+impl Header for ProxyAuthenticate {
+    fn name() -> &'static str {
+        "Proxy-Authenticate"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        parse_challenges::<Self>(value).map(ProxyAuthenticate)
+    }
+
+    fn value(&self) -> String {
+        self.0
+            .iter()
+            .map(Challenge::format)
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyAuthorization(pub Challenge);
+
+impl Header for ProxyAuthorization {
+    fn name() -> &'static str {
+        "Proxy-Authorization"
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        Authorization::parse(value)
+            .map(|authorization| ProxyAuthorization(authorization.0))
+            .map_err(|_| invalid::<Self>())
+    }
+
+    fn value(&self) -> String {
+        self.0.format()
+    }
+}
+
+fn invalid<H: Header>() -> Error {
+    Error::InvalidHeader(H::name().to_string())
+}
+
+/// RFC 9110 section 5.6.2.
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn is_token68(value: &str) -> bool {
+    let trimmed = value.trim_end_matches('=');
+    !trimmed.is_empty()
+        && trimmed
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b))
+}
+
+/// Returns `value` as is when it is a token, quoted otherwise.
+fn quote(value: &str) -> String {
+    if is_token(value) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return value.to_string();
+    }
+    let mut unquoted = String::with_capacity(value.len());
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Splits on `separator` outside of quoted strings and `<...>`, trimming the
+/// pieces and dropping empty ones.
+fn split_quoted(value: &str, separator: char) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut angle = false;
+    for (idx, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            c if c == separator && !quoted && !angle => {
+                pieces.push(value[start..idx].trim());
+                start = idx + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    pieces.push(value[start..].trim());
+    pieces.retain(|piece| !piece.is_empty());
+    pieces
+}
+
+fn parse_param<H: Header>(param: &str) -> Result<(String, String)> {
+    let mut split = param.splitn(2, '=');
+    let name = split.next().unwrap_or_default().trim();
+    let value = split.next().ok_or_else(invalid::<H>)?.trim();
+    if !is_token(name) {
+        return Err(invalid::<H>());
+    }
+    Ok((name.to_ascii_lowercase(), unquote(value)))
+}
+
+fn parse_codings<H: Header>(value: &str) -> Result<Vec<String>> {
+    let codings: Vec<String> = split_quoted(value, ',')
+        .into_iter()
+        .map(|coding| coding.to_ascii_lowercase())
+        .collect();
+    if codings.is_empty() || !codings.iter().all(|coding| is_token(coding)) {
+        return Err(invalid::<H>());
+    }
+    Ok(codings)
+}
+
+/// Parses a list of challenges. A list element that starts with a token
+/// followed by a space begins a new challenge, any other element is an
+/// `auth-param` of the current one. A token68 can only end in `=`, which
+/// tells it apart from a first `auth-param`.
+fn parse_challenges<H: Header>(value: &str) -> Result<Vec<Challenge>> {
+    let mut challenges: Vec<Challenge> = Vec::new();
+    for element in split_quoted(value, ',') {
+        let scheme_end = element.find([' ', '\t'].as_ref());
+        let begins_challenge = match scheme_end {
+            Some(end) => is_token(&element[..end]),
+            None => is_token(element),
+        };
+        if begins_challenge {
+            let (scheme, rest) = match scheme_end {
+                Some(end) => (&element[..end], element[end..].trim()),
+                None => (element, ""),
+            };
+            let mut challenge = Challenge::new(scheme);
+            if !rest.is_empty() {
+                if is_token68(rest) {
+                    challenge.token68 = Some(rest.to_string());
+                } else {
+                    let (name, value) = parse_param::<H>(rest)?;
+                    challenge.params.push((name, value));
+                }
+            }
+            challenges.push(challenge);
+        } else {
+            let (name, value) = parse_param::<H>(element)?;
+            match challenges.last_mut() {
+                Some(challenge) if challenge.token68.is_none() => {
+                    challenge.params.push((name, value))
+                }
+                _ => return Err(invalid::<H>()),
+            }
+        }
+    }
+    Ok(challenges)
+}
+
+/// Parses the three formats of RFC 9110 section 5.6.7: IMF-fixdate, RFC 850
+/// and asctime. Years outside 1601 to 9999 and dates before the Unix epoch
+/// give `None`.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = value.split_whitespace().collect();
+    let (day, month, year, time) = match tokens.as_slice() {
+        [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+            (*day, *month, year.parse::<i64>().ok()?, *time)
+        }
+        [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+            let mut date = date.splitn(3, '-');
+            let (day, month) = (date.next()?, date.next()?);
+            let year = date.next()?;
+            if year.len() != 2 {
+                return None;
+            }
+            let year = year.parse::<i64>().ok()?;
+            (
+                day,
+                month,
+                if year < 70 { 2000 + year } else { 1900 + year },
+                *time,
+            )
+        }
+        [_, month, day, time, year] => (*day, *month, year.parse::<i64>().ok()?, *time),
+        _ => return None,
+    };
+    let day = day.parse::<i64>().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let mut time = time.splitn(3, ':').map(|part| {
+        if part.len() == 2 {
+            part.parse::<i64>().ok()
+        } else {
+            None
+        }
+    });
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1601..=9999).contains(&year)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day)
+        .checked_mul(86400)?
+        .checked_add(hour * 3600 + minute * 60 + second)?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+/// Formats `time` as an IMF-fixdate, the format to send.
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+    let days = seconds.div_euclid(86400);
+    let rest = seconds.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+
+    #[test]
+    fn typed_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(time)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(time));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 9223372036854775807 08:49:37 GMT"),
+            None
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 10000"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1600 08:49:37 GMT"), None);
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn typed_content_headers() {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", "Text/HTML; Charset=\"utf-8\"; q=\"a b\"");
+        let content_type = headers.typed::<ContentType>().unwrap();
+        assert_eq!(content_type.mime, "text/html");
+        assert_eq!(content_type.charset(), Some("utf-8"));
+        assert_eq!(content_type.value(), "text/html; charset=utf-8; q=\"a b\"");
+
+        headers.set_typed(ContentLength(42));
+        assert_eq!(headers.get("Content-Length"), Some("42".to_string()));
+        assert_eq!(ContentLength::parse("5, 5"), Ok(ContentLength(5)));
+        assert_eq!(
+            ContentLength::parse("5, 6"),
+            Err(Error::InvalidHeader("Content-Length".to_string()))
+        );
+        assert_eq!(ContentLength::parse("-1").ok(), None);
+
+        let encoding = TransferEncoding::parse("GZIP, chunked").unwrap();
+        assert_eq!(encoding.0, vec!["gzip", "chunked"]);
+        assert!(encoding.is_chunked());
+        assert_eq!(
+            ContentEncoding::parse("br").unwrap().value(),
+            "br".to_string()
+        );
+    }
+
+    #[test]
+    fn typed_caching() {
+        let cache_control =
+            CacheControl::parse("no-cache, max-age=60, max-stale, community=\"UCI\"").unwrap();
+        assert_eq!(
+            cache_control,
+            CacheControl {
+                no_cache: true,
+                max_age: Some(60),
+                max_stale: Some(None),
+                extensions: vec![("community".to_string(), Some("UCI".to_string()))],
+                ..CacheControl::default()
+            }
+        );
+        assert_eq!(
+            cache_control.value(),
+            "no-cache, max-age=60, max-stale, community=UCI"
+        );
+        assert!(CacheControl::parse("max-age=soon").is_err());
+
+        assert_eq!(ETag::parse("W/\"xyz\""), Ok(ETag::weak("xyz")));
+        assert_eq!(ETag::strong("xyz").value(), "\"xyz\"");
+        assert!(ETag::parse("xyz").is_err());
+        assert!(ETag::weak("a").weak_eq(&ETag::strong("a")));
+        assert!(!ETag::weak("a").strong_eq(&ETag::strong("a")));
+
+        assert_eq!(Expires::parse("0"), Ok(Expires(UNIX_EPOCH)));
+        assert_eq!(
+            RetryAfter::parse("120"),
+            Ok(RetryAfter::Delay(Duration::from_secs(120)))
+        );
+        let date = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        let retry = RetryAfter::parse("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(retry, RetryAfter::Date(date));
+        assert_eq!(
+            retry.delay_from(date - Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn typed_link_location() {
+        let link = Link::parse(
+            "<https://api.example.com/items?page=2>; rel=\"next last\", <https://api.example.com/items?page=1,2>; rel=prev",
+        )
+        .unwrap();
+        assert_eq!(link.0.len(), 2);
+        assert_eq!(
+            link.find("last").map(|link| link.uri.as_str()),
+            Some("https://api.example.com/items?page=2")
+        );
+        assert_eq!(
+            link.find("prev").map(|link| link.uri.as_str()),
+            Some("https://api.example.com/items?page=1,2")
+        );
+        assert_eq!(Link::parse(&link.value()), Ok(link));
+
+        assert_eq!(
+            Location::parse(" /login "),
+            Ok(Location("/login".to_string()))
+        );
+        assert!(Location::parse("/a b").is_err());
+    }
+
+    #[test]
+    fn typed_auth() {
+        let www_authenticate = WwwAuthenticate::parse(
+            "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", Basic realm=\"simple\"",
+        )
+        .unwrap();
+        assert_eq!(www_authenticate.0.len(), 2);
+        let newauth = &www_authenticate.0[0];
+        assert_eq!(newauth.realm(), Some("apps"));
+        assert_eq!(newauth.get_param("title"), Some("Login to \"apps\""));
+        assert_eq!(
+            www_authenticate.find("basic").and_then(Challenge::realm),
+            Some("simple")
+        );
+        assert_eq!(
+            WwwAuthenticate::parse(&www_authenticate.value()),
+            Ok(www_authenticate)
+        );
+
+        let authorization = Authorization::basic("Aladdin", "open sesame");
+        assert_eq!(authorization.value(), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        let parsed = Authorization::parse("basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==").unwrap();
+        assert_eq!(
+            parsed.basic_credentials(),
+            Some(("Aladdin".to_string(), "open sesame".to_string()))
+        );
+        assert_eq!(
+            Authorization::parse("Bearer abc.def")
+                .unwrap()
+                .bearer_token(),
+            Some("abc.def")
+        );
+        assert!(Authorization::parse("Basic a, Bearer b").is_err());
+    }
+}